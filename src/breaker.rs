@@ -0,0 +1,135 @@
+use log::warn;
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use crate::config::BreakerConfig;
+
+/// Per-host circuit breakers: after too many failures in a row we leave a
+/// host alone for a while, then try a single page to see if it's back.
+pub struct Breakers {
+    cfg: BreakerConfig,
+    hosts: HashMap<String, Breaker>,
+}
+
+#[derive(Default)]
+struct Breaker {
+    failures: usize,
+    open_until: Option<Instant>,
+    trips: usize,
+    skipped: usize,
+}
+
+/// What to do with a url on some host.
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Go,
+    /// the host is cooling down; come back later
+    Wait(Duration),
+    /// the host has tripped too often, give up on it
+    Skip,
+}
+
+/// A host whose breaker tripped during the crawl.
+pub struct Tripped {
+    pub trips: usize,
+    /// urls we never fetched because of it
+    pub skipped: usize,
+    pub closed: bool,
+}
+
+impl Breakers {
+    pub fn new(cfg: BreakerConfig) -> Self {
+        Self { cfg, hosts: HashMap::new() }
+    }
+
+    pub fn check(&mut self, host: &str) -> Verdict {
+        let max_trips = self.cfg.max_trips;
+        let b = match self.hosts.get_mut(host) {
+            Some(b) => b,
+            None => return Verdict::Go,
+        };
+
+        if b.trips >= max_trips && b.open_until.is_some() {
+            b.skipped += 1;
+            return Verdict::Skip;
+        }
+
+        match b.open_until {
+            Some(until) if until > Instant::now() => Verdict::Wait(until - Instant::now()),
+            // cooled down: let this one through as a probe; how it goes
+            // closes the breaker or opens it again
+            _ => Verdict::Go,
+        }
+    }
+
+    pub fn record(&mut self, host: &str, failed: bool) {
+        if !failed {
+            if let Some(b) = self.hosts.get_mut(host) {
+                b.failures = 0;
+                b.open_until = None;
+            }
+            return;
+        }
+
+        let b = self.hosts.entry(host.to_string()).or_default();
+        b.failures += 1;
+
+        // a failed probe opens the breaker again right away
+        if b.failures >= self.cfg.threshold || b.open_until.is_some() {
+            b.trips += 1;
+            b.open_until = Some(Instant::now() + Duration::from_secs(self.cfg.cooldown_secs));
+            warn!("{} failed {} times in a row, leaving it alone for {}s",
+                host, b.failures, self.cfg.cooldown_secs);
+        }
+    }
+
+    /// host -> how its breaker did, for the hosts that tripped
+    pub fn tripped(&self) -> BTreeMap<&str, Tripped> {
+        self.hosts.iter()
+            .filter(|(_, b)| b.trips > 0)
+            .map(|(host, b)| (host.as_str(), Tripped {
+                trips: b.trips,
+                skipped: b.skipped,
+                closed: b.open_until.is_none(),
+            }))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trip() {
+        let mut breakers = Breakers::new(BreakerConfig {
+            threshold: 2,
+            cooldown_secs: 60,
+            max_trips: 2,
+        });
+
+        breakers.record("a", true);
+        assert_eq!(breakers.check("a"), Verdict::Go);
+        breakers.record("a", true);
+        assert!(matches!(breakers.check("a"), Verdict::Wait(_)));
+        assert_eq!(breakers.check("b"), Verdict::Go);
+
+        // pretend it cooled down and the probe failed
+        breakers.hosts.get_mut("a").unwrap().open_until = Some(Instant::now());
+        assert_eq!(breakers.check("a"), Verdict::Go);
+        breakers.record("a", true);
+        assert_eq!(breakers.check("a"), Verdict::Skip);
+
+        let tripped = breakers.tripped();
+        assert_eq!((tripped["a"].trips, tripped["a"].skipped), (2, 1));
+
+        // a host that comes back closes its breaker
+        breakers.record("c", true);
+        breakers.record("c", true);
+        breakers.hosts.get_mut("c").unwrap().open_until = Some(Instant::now());
+        breakers.record("c", false);
+        assert_eq!(breakers.check("c"), Verdict::Go);
+        assert!(breakers.tripped()["c"].closed);
+    }
+}
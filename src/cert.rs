@@ -0,0 +1,38 @@
+use rustls::{Certificate, PrivateKey};
+
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+
+/// Load a certificate chain and its private key (PKCS#8 or RSA) from PEM
+/// files.
+pub fn load_pem(cert_path: &str, key_path: &str)
+    -> Result<(Vec<Certificate>, PrivateKey), Box<dyn Error>>
+{
+    use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+
+    let chain = certs(&mut BufReader::new(File::open(cert_path)?))
+        .map_err(|_| format!("couldn't read certificates from {}", cert_path))?;
+    if chain.is_empty() {
+        Err(format!("no certificates found in {}", cert_path))?;
+    }
+
+    let mut keys = pkcs8_private_keys(&mut BufReader::new(File::open(key_path)?))
+        .map_err(|_| format!("couldn't read private key from {}", key_path))?;
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut BufReader::new(File::open(key_path)?))
+            .map_err(|_| format!("couldn't read private key from {}", key_path))?;
+    }
+
+    match keys.into_iter().next() {
+        Some(key) => Ok((chain, key)),
+        None => Err(format!("no private key found in {}", key_path))?,
+    }
+}
+
+/// Generate a self-signed certificate for the given names.
+pub fn generate(names: Vec<String>) -> Result<(Vec<Certificate>, PrivateKey), Box<dyn Error>> {
+    let cert = rcgen::generate_simple_self_signed(names)?;
+    Ok((vec![Certificate(cert.serialize_der()?)],
+        PrivateKey(cert.serialize_private_key_der())))
+}
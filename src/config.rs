@@ -0,0 +1,173 @@
+use log::LevelFilter;
+use serde::Deserialize;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::net::IpAddr;
+
+use crate::error::ErrorClass;
+
+/// Settings for a crawl, read from a JSON file given with `--config`.
+/// Everything is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub identities: Vec<IdentityConfig>,
+    pub gopher: GopherConfig,
+    pub net: NetConfig,
+    pub retry: RetryConfig,
+    pub breaker: BreakerConfig,
+    pub log: LogConfig,
+    pub metrics: MetricsConfig,
+}
+
+/// Whether to follow gopher:// links, and how far.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct GopherConfig {
+    pub enabled: bool,
+    pub max_pages: usize,
+}
+
+impl Default for GopherConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_pages: 1000,
+        }
+    }
+}
+
+/// How we resolve hosts and how often we connect to them.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct NetConfig {
+    /// how long resolved addresses are used before looking them up again
+    pub dns_ttl_secs: u64,
+    /// minimum time between connections to the same address
+    pub per_ip_delay_ms: u64,
+    /// host name -> address to use instead of resolving it
+    pub hosts: HashMap<String, IpAddr>,
+    /// the first proxy matching a host is used for it
+    pub proxies: Vec<ProxyConfig>,
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        Self {
+            dns_ttl_secs: 300,
+            per_ip_delay_ms: 0,
+            hosts: HashMap::new(),
+            proxies: Vec::new(),
+        }
+    }
+}
+
+/// How often to try pages again that failed in ways that might go away,
+/// counting the first try. Failures not listed here aren't retried.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    pub timeout: usize,
+    /// error class -> attempts
+    pub errors: HashMap<ErrorClass, usize>,
+    /// response code -> attempts
+    pub statuses: HashMap<usize, usize>,
+    /// the wait before the first retry, doubled for each one after that
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            timeout: 3,
+            errors: vec![(ErrorClass::ConnectionReset, 3)].into_iter().collect(),
+            statuses: vec![(40, 3), (41, 3), (42, 2), (43, 2), (44, 5)].into_iter().collect(),
+            base_delay_ms: 2000,
+            max_delay_ms: 120_000,
+        }
+    }
+}
+
+/// When to stop trying a host for a while: after `threshold` failures in a
+/// row, for `cooldown_secs`, and for good once it has happened `max_trips`
+/// times.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct BreakerConfig {
+    pub threshold: usize,
+    pub cooldown_secs: u64,
+    pub max_trips: usize,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 5,
+            cooldown_secs: 300,
+            max_trips: 3,
+        }
+    }
+}
+
+/// What to log, and where besides stderr.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    pub level: LevelFilter,
+    /// module (e.g. `net` or `rustls`) -> level for it and its submodules
+    pub modules: HashMap<String, LevelFilter>,
+    /// a file to append JSON lines to
+    pub file: Option<String>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: LevelFilter::Info,
+            modules: HashMap::new(),
+            file: None,
+        }
+    }
+}
+
+/// Where to serve metrics over HTTP, in the Prometheus text format; by
+/// default we don't.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub listen: Option<String>,
+}
+
+/// A SOCKS5 proxy for the hosts matching a pattern: a host name, `*.domain`
+/// for everything below a domain, or `*` for all hosts.
+#[derive(Debug, Deserialize)]
+pub struct ProxyConfig {
+    pub hosts: String,
+    /// host:port of the proxy
+    pub socks5: String,
+}
+
+/// A client certificate to present to a capsule (or part of one) that asks
+/// for it with status 60. Without `cert` and `key`, a certificate is
+/// generated for the crawl.
+#[derive(Debug, Deserialize)]
+pub struct IdentityConfig {
+    pub host: String,
+    #[serde(default = "root")]
+    pub path: String,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+}
+
+fn root() -> String {
+    "/".to_string()
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+
+use crate::UrlInfo;
+
+/// Why a fetch didn't get us a (well-formed) response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    InvalidHost,
+    DnsFailure,
+    ConnectionRefused,
+    ConnectionReset,
+    Unreachable,
+    Proxy,
+    TlsHandshake,
+    InvalidSniName,
+    MalformedHeader,
+    TruncatedBody,
+    Other,
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ErrorClass::InvalidHost => "invalid host",
+            ErrorClass::DnsFailure => "DNS failure",
+            ErrorClass::ConnectionRefused => "connection refused",
+            ErrorClass::ConnectionReset => "connection reset",
+            ErrorClass::Unreachable => "unreachable",
+            ErrorClass::Proxy => "proxy error",
+            ErrorClass::TlsHandshake => "TLS handshake failed",
+            ErrorClass::InvalidSniName => "invalid SNI name",
+            ErrorClass::MalformedHeader => "malformed header",
+            ErrorClass::TruncatedBody => "truncated body",
+            ErrorClass::Other => "error",
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FetchError {
+    pub class: ErrorClass,
+    pub message: String,
+}
+
+impl FetchError {
+    pub fn new(class: ErrorClass, message: impl Into<String>) -> Self {
+        Self { class, message: message.into() }
+    }
+
+    /// Classify an io error by its kind, and say where it happened.
+    pub fn io(e: io::Error, context: &str) -> Self {
+        let class = match e.kind() {
+            io::ErrorKind::ConnectionRefused => ErrorClass::ConnectionRefused,
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe => ErrorClass::ConnectionReset,
+            io::ErrorKind::UnexpectedEof => ErrorClass::TruncatedBody,
+            io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::AddrNotAvailable => ErrorClass::Unreachable,
+            _ => ErrorClass::Other,
+        };
+        Self::new(class, format!("{}: {}", context, e))
+    }
+
+    /// An error while reading a response: once part of it has arrived,
+    /// losing the connection means we only have some of the body.
+    pub fn reading(e: io::Error, received: &[u8]) -> Self {
+        if received.is_empty() {
+            Self::io(e, "reading response")
+        } else {
+            Self::new(ErrorClass::TruncatedBody,
+                format!("reading response after {} bytes: {}", received.len(), e))
+        }
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.class)
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+/// error class -> number of pages that failed with it
+pub fn counts(entries: &HashMap<String, UrlInfo>) -> BTreeMap<ErrorClass, usize> {
+    let mut counts = BTreeMap::new();
+    for e in entries.values().filter_map(|i| i.error.as_ref()) {
+        *counts.entry(e.class).or_insert(0) += 1;
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify() {
+        let refused = io::Error::from(io::ErrorKind::ConnectionRefused);
+        assert_eq!(FetchError::io(refused, "a:1965").class, ErrorClass::ConnectionRefused);
+        let eof = io::Error::from(io::ErrorKind::UnexpectedEof);
+        assert_eq!(FetchError::io(eof, "reading").class, ErrorClass::TruncatedBody);
+
+        let mut entries = HashMap::new();
+        for (i, class) in [ErrorClass::DnsFailure, ErrorClass::DnsFailure, ErrorClass::TlsHandshake]
+            .iter().enumerate()
+        {
+            let info = UrlInfo { error: Some(FetchError::new(*class, "x")), ..Default::default() };
+            entries.insert(i.to_string(), info);
+        }
+        entries.insert("ok".to_string(), UrlInfo::default());
+
+        let counts = counts(&entries);
+        assert_eq!(counts[&ErrorClass::DnsFailure], 2);
+        assert_eq!(counts[&ErrorClass::TlsHandshake], 1);
+        assert_eq!(counts.len(), 2);
+    }
+}
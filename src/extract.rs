@@ -0,0 +1,245 @@
+use gemtext::Node;
+
+/// A link as written in a document: where it points to (possibly relative)
+/// and the text it is shown with, if any.
+pub type Link = (String, Option<String>);
+
+// schemes we look for in bare text
+const SCHEMES: &[&str] = &["gemini://", "gopher://", "https://", "http://"];
+
+/// Find the links in a text body of the given MIME type, or `None` if we
+/// don't know how to find links in that type.
+pub fn links(essence: &str, text: &str) -> Option<Vec<Link>> {
+    match essence {
+        "text/gemini" => Some(gemtext_links(&gemtext::parse(text))),
+        "text/plain" => Some(bare_links(text)),
+        "text/markdown" | "text/x-markdown" => Some(markdown_links(text)),
+        "text/html" => Some(html_links(text)),
+        _ => None,
+    }
+}
+
+pub fn gemtext_links(nodes: &[Node]) -> Vec<Link> {
+    nodes.iter()
+        .filter_map(|n| match n {
+            Node::Link { to, name } => Some((to.clone(), name.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Absolute urls anywhere in the text.
+pub fn bare_links(text: &str) -> Vec<Link> {
+    let mut found = Vec::new();
+    let lower = text.to_ascii_lowercase();
+    let mut i = 0;
+
+    while i < text.len() {
+        let start = match SCHEMES.iter().filter_map(|s| lower[i..].find(s)).min() {
+            Some(s) => i + s,
+            None => break,
+        };
+
+        let len = text[start..]
+            .find(|c: char| c.is_whitespace() || "<>\"'`".contains(c))
+            .unwrap_or(text.len() - start);
+        let url = text[start..start + len]
+            .trim_end_matches(|c: char| ".,;:!?)]}".contains(c));
+
+        if !SCHEMES.iter().any(|s| url.eq_ignore_ascii_case(s)) {
+            found.push((url.to_string(), None));
+        }
+        i = start + len.max(1);
+    }
+
+    found
+}
+
+/// Inline links `[name](url)`, reference definitions `[name]: url` and
+/// autolinks `<url>`.
+pub fn markdown_links(text: &str) -> Vec<Link> {
+    let mut found = Vec::new();
+
+    for line in text.lines() {
+        // reference definitions take up the whole line
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('[') {
+            if let Some(end) = trimmed.find("]:") {
+                let name = &trimmed[1..end];
+                if let Some(url) = trimmed[end + 2..].split_whitespace().next() {
+                    let url = url.trim_start_matches('<').trim_end_matches('>');
+                    found.push((url.to_string(), Some(name.to_string())));
+                    continue;
+                }
+            }
+        }
+
+        let mut rest = line;
+        while let Some(open) = rest.find(['[', '<']) {
+            if rest[open..].starts_with('<') {
+                let inner = &rest[open + 1..];
+                match inner.find('>') {
+                    Some(close) if inner[..close].contains("://") => {
+                        found.push((inner[..close].to_string(), None));
+                        rest = &inner[close + 1..];
+                    },
+                    _ => rest = inner,
+                }
+                continue;
+            }
+
+            // a [ that doesn't start a link is just text; look past it
+            let inner = &rest[open + 1..];
+            let close = match inner.find(']') {
+                Some(c) if inner[c..].starts_with("](") => c,
+                _ => { rest = inner; continue; },
+            };
+            let target = &inner[close + 2..];
+            let end = match target.find(')') {
+                Some(e) => e,
+                None => { rest = inner; continue; },
+            };
+
+            // [name](url "title")
+            let url = target[..end].split_whitespace().next().unwrap_or("");
+            if !url.is_empty() {
+                let name = inner[..close].trim_start_matches('!').trim();
+                let name = if name.is_empty() { None } else { Some(name.to_string()) };
+                found.push((url.trim_start_matches('<').trim_end_matches('>').to_string(), name));
+            }
+            rest = &target[end + 1..];
+        }
+    }
+
+    found
+}
+
+/// The `href` of every `<a>` tag, named by the text inside it.
+pub fn html_links(text: &str) -> Vec<Link> {
+    let mut found = Vec::new();
+    let lower = text.to_ascii_lowercase();
+    let mut i = 0;
+
+    while let Some(start) = lower[i..].find("<a").map(|s| i + s) {
+        i = start + 2;
+
+        // make sure this is an <a> and not e.g. <abbr>
+        match lower[i..].chars().next() {
+            Some(c) if c.is_whitespace() => (),
+            _ => continue,
+        }
+
+        let tag_end = match lower[i..].find('>') {
+            Some(e) => i + e,
+            None => break,
+        };
+        let href = match attribute(&text[i..tag_end], &lower[i..tag_end], "href") {
+            Some(h) => html_unescape(&h),
+            None => continue,
+        };
+
+        let content_end = lower[tag_end..].find("</a").map(|e| tag_end + e)
+            .unwrap_or(text.len());
+        let name = strip_tags(&text[tag_end + 1..content_end]);
+        let name = if name.is_empty() { None } else { Some(name) };
+
+        found.push((href, name));
+        i = tag_end;
+    }
+
+    found
+}
+
+fn attribute(tag: &str, lower: &str, name: &str) -> Option<String> {
+    let mut i = 0;
+    while let Some(pos) = lower[i..].find(name).map(|p| i + p) {
+        i = pos + name.len();
+
+        // has to be a whole attribute name, followed by =
+        let before = lower[..pos].chars().last();
+        if !before.map(|c| c.is_whitespace()).unwrap_or(false) {
+            continue;
+        }
+        let rest = lower[i..].trim_start();
+        if !rest.starts_with('=') {
+            continue;
+        }
+
+        let value_start = tag.len() - rest.len() + 1;
+        let value = tag[value_start..].trim_start();
+        return Some(match value.chars().next() {
+            Some(q) if q == '"' || q == '\'' => {
+                value[1..].split(q).next().unwrap_or("").to_string()
+            },
+            _ => value.split_whitespace().next().unwrap_or("").to_string(),
+        });
+    }
+
+    None
+}
+
+fn strip_tags(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => (),
+        }
+    }
+
+    html_unescape(&text.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+fn html_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(links: Vec<Link>) -> Vec<String> {
+        links.into_iter().map(|(u, _)| u).collect()
+    }
+
+    #[test]
+    fn plain() {
+        let text = "see gemini://a.example/x, or (https://b.example/y).\nGOPHER://c.example/ gemini://";
+        assert_eq!(urls(bare_links(text)),
+            vec!["gemini://a.example/x", "https://b.example/y", "GOPHER://c.example/"]);
+    }
+
+    #[test]
+    fn markdown() {
+        let text = "a [link](gemini://a.example/ \"title\") and ![img](/i.png)\n\
+                    [ref]: gemini://b.example/\n\
+                    an <gemini://c.example/> autolink\n\
+                    see [1] and <gemini://x.example/>\n\
+                    a [note] then [real](gemini://y.example/)";
+        assert_eq!(markdown_links(text), vec![
+            ("gemini://a.example/".to_string(), Some("link".to_string())),
+            ("/i.png".to_string(), Some("img".to_string())),
+            ("gemini://b.example/".to_string(), Some("ref".to_string())),
+            ("gemini://c.example/".to_string(), None),
+            ("gemini://x.example/".to_string(), None),
+            ("gemini://y.example/".to_string(), Some("real".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn html() {
+        let text = "<p><abbr>x</abbr><A class=x HREF=\"gemini://a.example/?a=1&amp;b=2\">the <b>a</b>\n\
+                    capsule</a> <a href='/rel'>rel</a> <a name=top>";
+        assert_eq!(html_links(text), vec![
+            ("gemini://a.example/?a=1&b=2".to_string(), Some("the a capsule".to_string())),
+            ("/rel".to_string(), Some("rel".to_string())),
+        ]);
+    }
+}
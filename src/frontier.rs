@@ -0,0 +1,232 @@
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The urls left to crawl: those we can fetch right away, those we put
+/// off until later (e.g. to retry them), and those waiting for another url
+/// to be fetched first.
+#[derive(Default)]
+pub struct Frontier {
+    queue: Vec<Url>,
+    deferred: Vec<(Instant, Url)>,
+    // url -> the urls waiting for it
+    waiting: HashMap<String, Vec<Url>>,
+}
+
+/// A url in the saved frontier.
+#[derive(Debug, Deserialize, Serialize)]
+struct Saved {
+    url: String,
+    // unix time in ms, for urls that were put off
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    not_before: Option<u64>,
+    // for urls waiting for another one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    after: Option<String>,
+}
+
+impl Frontier {
+    /// The urls saved with `save`, put off or waiting as they were.
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let saved: Vec<Saved> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let (now, now_ms) = (Instant::now(), unix_ms());
+
+        let mut frontier = Self::default();
+        for s in saved {
+            let url = Url::parse(&s.url)?;
+            match (s.not_before, s.after) {
+                (Some(at), _) => frontier.deferred.push(
+                    (now + Duration::from_millis(at.saturating_sub(now_ms)), url)),
+                (None, Some(after)) => frontier.waiting.entry(after).or_default().push(url),
+                (None, None) => frontier.queue.push(url),
+            }
+        }
+        Ok(frontier)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let (now, now_ms) = (Instant::now(), unix_ms());
+
+        let deferred = self.deferred.iter().map(|(at, url)| Saved {
+            url: url.to_string(),
+            not_before: Some(now_ms + at.saturating_duration_since(now).as_millis() as u64),
+            after: None,
+        });
+        let waiting = self.waiting.iter().flat_map(|(after, urls)| urls.iter().map(move |url| Saved {
+            url: url.to_string(),
+            not_before: None,
+            after: Some(after.clone()),
+        }));
+        let queue = self.queue.iter().map(|url| Saved {
+            url: url.to_string(),
+            not_before: None,
+            after: None,
+        });
+
+        let saved = deferred.chain(waiting).chain(queue).collect::<Vec<_>>();
+        crate::write_file(path, serde_json::to_string(&saved)?.as_bytes())
+    }
+
+    pub fn push(&mut self, url: Url) {
+        self.queue.push(url);
+    }
+
+    /// Put a url back, to be fetched no sooner than `delay` from now.
+    pub fn defer(&mut self, url: Url, delay: Duration) {
+        self.deferred.push((Instant::now() + delay, url));
+    }
+
+    /// Hold a url back until `first` has been fetched.
+    pub fn wait_for(&mut self, first: &Url, url: Url) {
+        self.waiting.entry(first.to_string()).or_default().push(url);
+    }
+
+    /// We've fetched `url`, so whatever waited for it can go.
+    pub fn fetched(&mut self, url: &Url) {
+        if let Some(urls) = self.waiting.remove(url.as_str()) {
+            self.queue.extend(urls);
+        }
+    }
+
+    /// The next url to fetch, if any is due.
+    pub fn pop(&mut self) -> Option<Url> {
+        let now = Instant::now();
+        let mut i = 0;
+        while i < self.deferred.len() {
+            if self.deferred[i].0 <= now {
+                let (_, url) = self.deferred.swap_remove(i);
+                self.queue.push(url);
+            } else {
+                i += 1;
+            }
+        }
+
+        self.queue.pop()
+    }
+
+    /// How long until a deferred url is due.
+    pub fn wait(&self) -> Option<Duration> {
+        let now = Instant::now();
+        self.deferred.iter()
+            .map(|(at, _)| at.saturating_duration_since(now))
+            .min()
+    }
+
+    /// The hosts with the most urls waiting, and how many.
+    pub fn top_hosts(&self, n: usize) -> Vec<(String, usize)> {
+        let mut hosts: HashMap<&str, usize> = HashMap::new();
+        let urls = self.queue.iter().chain(self.deferred.iter().map(|(_, u)| u));
+        for url in urls {
+            *hosts.entry(url.host_str().unwrap_or("")).or_insert(0) += 1;
+        }
+
+        let mut hosts = hosts.into_iter()
+            .map(|(h, n)| (h.to_string(), n))
+            .collect::<Vec<_>>();
+        hosts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hosts.truncate(n);
+        hosts
+    }
+
+    /// Every url in the frontier, waiting or not.
+    pub fn urls(&self) -> impl Iterator<Item = &Url> {
+        self.queue.iter()
+            .chain(self.deferred.iter().map(|(_, u)| u))
+            .chain(self.waiting.values().flatten())
+    }
+
+    /// Not counting the urls waiting for others, which may wait forever.
+    pub fn len(&self) -> usize {
+        self.queue.len() + self.deferred.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(u: &str) -> Url {
+        Url::parse(u).unwrap()
+    }
+
+    #[test]
+    fn deferred() {
+        let mut frontier = Frontier::default();
+        frontier.push(url("gemini://a/1"));
+        frontier.defer(url("gemini://a/later"), Duration::from_secs(60));
+        frontier.defer(url("gemini://a/soon"), Duration::from_secs(5));
+        assert_eq!(frontier.len(), 3);
+
+        // the one due soonest says how long to wait
+        let wait = frontier.wait().unwrap();
+        assert!(wait > Duration::from_secs(4) && wait <= Duration::from_secs(5), "{:?}", wait);
+
+        // what's put off isn't popped before it's due
+        assert_eq!(frontier.pop(), Some(url("gemini://a/1")));
+        assert_eq!(frontier.pop(), None);
+        assert!(!frontier.is_empty());
+
+        // once due, it comes before what was queued already
+        frontier.push(url("gemini://a/2"));
+        frontier.defer(url("gemini://a/now"), Duration::from_secs(0));
+        assert_eq!(frontier.wait(), Some(Duration::from_secs(0)));
+        assert_eq!(frontier.pop(), Some(url("gemini://a/now")));
+        assert_eq!(frontier.pop(), Some(url("gemini://a/2")));
+        assert_eq!(frontier.len(), 2);
+
+        assert_eq!(Frontier::default().wait(), None);
+    }
+
+    #[test]
+    fn waiting() {
+        let mut frontier = Frontier::default();
+        frontier.push(url("gemini://a/login"));
+        frontier.wait_for(&url("gemini://a/login"), url("gemini://a/login?x"));
+
+        assert_eq!(frontier.len(), 1);
+        assert_eq!(frontier.pop(), Some(url("gemini://a/login")));
+        assert_eq!(frontier.pop(), None);
+
+        frontier.fetched(&url("gemini://a/login"));
+        assert_eq!(frontier.pop(), Some(url("gemini://a/login?x")));
+    }
+
+    #[test]
+    fn save_load() {
+        let mut frontier = Frontier::default();
+        frontier.push(url("gemini://a/1"));
+        frontier.push(url("gemini://a/2"));
+        frontier.defer(url("gemini://a/later"), Duration::from_secs(60));
+        frontier.wait_for(&url("gemini://a/login"), url("gemini://a/login?x"));
+
+        let path = std::env::temp_dir().join(format!("gc-frontier-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        frontier.save(path).unwrap();
+        let mut loaded = Frontier::load(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        // still put off by about as long
+        let wait = loaded.wait().unwrap();
+        assert!(wait > Duration::from_secs(58) && wait <= Duration::from_secs(60), "{:?}", wait);
+
+        assert_eq!(loaded.pop(), Some(url("gemini://a/2")));
+        assert_eq!(loaded.pop(), Some(url("gemini://a/1")));
+        assert_eq!(loaded.pop(), None);
+        loaded.fetched(&url("gemini://a/login"));
+        assert_eq!(loaded.pop(), Some(url("gemini://a/login?x")));
+    }
+}
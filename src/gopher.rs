@@ -0,0 +1,165 @@
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use url::Url;
+
+use crate::error::FetchError;
+use crate::extract::Link;
+use crate::net::{Host, Resolver};
+
+pub const DEFAULT_PORT: u16 = 70;
+
+// what to escape so that a selector survives in the path of a url as it is
+const SELECTOR: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'%')
+    .add(b'<').add(b'>').add(b'?').add(b'`').add(b'{').add(b'}');
+
+/// The item type of a gopher url, i.e. the first character of its path;
+/// the root of a server is a menu.
+pub fn item_type(url: &Url) -> char {
+    url.path().chars().nth(1).unwrap_or('1')
+}
+
+/// The selector of a gopher url, i.e. its path without the item type.
+pub fn selector(url: &Url) -> String {
+    let path = url.path();
+    let selector = path.char_indices().nth(2)
+        .map(|(i, _)| &path[i..])
+        .unwrap_or("");
+
+    let mut selector = percent_decode_str(selector).decode_utf8_lossy().to_string();
+    if let Some(q) = url.query() {
+        // search items (type 7) take the query after a tab
+        selector.push('\t');
+        selector.push_str(&percent_decode_str(q).decode_utf8_lossy());
+    }
+    selector
+}
+
+/// A MIME type for what we can expect behind an item type.
+pub fn mime_for(item_type: char) -> &'static str {
+    match item_type {
+        '0' => "text/plain",
+        '1' | '7' => "application/gopher-menu",
+        'h' => "text/html",
+        'g' => "image/gif",
+        'I' | 'p' => "image/*",
+        's' => "audio/*",
+        _ => "application/octet-stream",
+    }
+}
+
+pub async fn get(url: &Url, resolver: &Resolver) -> Result<Vec<u8>, FetchError> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let host = Host::of(url)?;
+    let port = url.port_or_known_default().unwrap_or(DEFAULT_PORT);
+
+    let mut sock = resolver.connect(&host, port).await?;
+    sock.write_all(format!("{}\r\n", selector(url)).as_bytes()).await
+        .map_err(|e| FetchError::io(e, "sending selector"))?;
+
+    let mut buf: Vec<u8> = vec![];
+    if let Err(e) = sock.read_to_end(&mut buf).await {
+        return Err(FetchError::reading(e, &buf));
+    }
+
+    Ok(buf)
+}
+
+/// The links in a gophermap, as gopher urls, except for `URL:` selectors
+/// which point elsewhere directly, and telnet items, as telnet urls.
+pub fn menu_links(menu: &str) -> Vec<Link> {
+    let mut found = Vec::new();
+
+    for line in menu.lines() {
+        if line == "." {
+            break;
+        }
+
+        let mut chars = line.chars();
+        let item_type = match chars.next() {
+            // informational lines and errors
+            Some('i') | Some('3') | None => continue,
+            Some(t) => t,
+        };
+
+        let fields = chars.as_str().split('\t').collect::<Vec<_>>();
+        if fields.len() < 4 {
+            continue;
+        }
+        let (name, selector, host, port) = (fields[0], fields[1], fields[2], fields[3].trim());
+        let name = if name.trim().is_empty() { None } else { Some(name.trim().to_string()) };
+
+        if let Some(url) = selector.strip_prefix("URL:") {
+            found.push((url.to_string(), name));
+            continue;
+        }
+
+        if host.is_empty() {
+            continue;
+        }
+
+        match item_type {
+            // telnet sessions aren't gopher, but they're somewhere to go
+            '8' | 'T' => {
+                found.push((format!("telnet://{}:{}", host, port), name));
+                continue;
+            },
+            // nor is a CSO phone book
+            '2' => continue,
+            _ => (),
+        }
+
+        // selectors are opaque, so they go after the item type as they are
+        let selector = utf8_percent_encode(selector, SELECTOR);
+        found.push((format!("gopher://{}:{}/{}{}", host, port, item_type, selector), name));
+    }
+
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls() {
+        let url = Url::parse("gopher://a.example/0/docs/read%20me.txt").unwrap();
+        assert_eq!(item_type(&url), '0');
+        assert_eq!(selector(&url), "/docs/read me.txt");
+
+        // the url crate knows 70 is the default port and leaves it out
+        let root = Url::parse("gopher://a.example:70").unwrap();
+        assert_eq!(root.as_str(), "gopher://a.example/");
+        assert_eq!(item_type(&root), '1');
+        assert_eq!(selector(&root), "");
+
+        let search = Url::parse("gopher://a.example/7/search?rust").unwrap();
+        assert_eq!(selector(&search), "/search\trust");
+
+        let bare = Url::parse("gopher://a.example/0about.txt").unwrap();
+        assert_eq!(selector(&bare), "about.txt");
+    }
+
+    #[test]
+    fn menu() {
+        let menu = "iWelcome\t\terror.host\t1\r\n\
+                    1Phlog\t/phlog\ta.example\t70\r\n\
+                    0About\tabout.txt\ta.example\t7070\r\n\
+                    0FAQ\tfaq?all #1\ta.example\t70\r\n\
+                    hWeb\tURL:https://b.example/\ta.example\t70\r\n\
+                    3oops\t\terror.host\t1\r\n\
+                    8Telnet\t\thost\t23\r\n\
+                    2Phones\t\tcso.example\t105\r\n\
+                    .\r\n\
+                    1after\t/x\ta.example\t70\r\n";
+        assert_eq!(menu_links(menu), vec![
+            ("gopher://a.example:70/1/phlog".to_string(), Some("Phlog".to_string())),
+            ("gopher://a.example:7070/0about.txt".to_string(), Some("About".to_string())),
+            ("gopher://a.example:70/0faq%3Fall%20%231".to_string(), Some("FAQ".to_string())),
+            ("https://b.example/".to_string(), Some("Web".to_string())),
+            ("telnet://host:23".to_string(), Some("Telnet".to_string())),
+        ]);
+
+        let faq = Url::parse(&menu_links(menu)[2].0).unwrap();
+        assert_eq!(selector(&faq), "faq?all #1");
+    }
+}
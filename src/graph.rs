@@ -0,0 +1,289 @@
+use url::Url;
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io::{self, Write};
+
+use crate::UrlInfo;
+
+/// A directed link graph built up from the `referred_from` lists in the
+/// crawl results, either with one vertex per page or one per capsule.
+pub struct Graph {
+    pub vertices: Vec<Vertex>,
+    index: HashMap<String, usize>,
+    /// (from, to) -> number of links
+    pub edges: BTreeMap<(usize, usize), usize>,
+}
+
+pub struct Vertex {
+    pub label: String,
+    pub response_code: usize,
+    pub metatext: String,
+    pub title: String,
+    pub pages: usize,
+}
+
+impl Graph {
+    fn new() -> Self {
+        Self {
+            vertices: Vec::new(),
+            index: HashMap::new(),
+            edges: BTreeMap::new(),
+        }
+    }
+
+    fn vertex(&mut self, label: &str) -> usize {
+        if let Some(&i) = self.index.get(label) {
+            return i;
+        }
+
+        self.vertices.push(Vertex {
+            label: label.to_string(),
+            response_code: 0,
+            metatext: String::new(),
+            title: String::new(),
+            pages: 0,
+        });
+        self.index.insert(label.to_string(), self.vertices.len() - 1);
+        self.vertices.len() - 1
+    }
+
+    fn edge(&mut self, from: usize, to: usize) {
+        *self.edges.entry((from, to)).or_insert(0) += 1;
+    }
+
+    /// One vertex per crawled url.
+    pub fn pages(entries: &HashMap<String, UrlInfo>) -> Self {
+        let mut graph = Self::new();
+
+        // sort, so that the vertex ids are stable between runs
+        let mut urls = entries.keys().collect::<Vec<_>>();
+        urls.sort();
+
+        for url in urls {
+            let info = &entries[url];
+            let to = graph.vertex(url);
+            graph.vertices[to].response_code = info.response_code;
+            graph.vertices[to].metatext = info.metatext.clone();
+            graph.vertices[to].title = info.title.clone().unwrap_or_default();
+            graph.vertices[to].pages = 1;
+
+            for referrer in &info.referred_from {
+                let from = graph.vertex(referrer);
+                graph.edge(from, to);
+            }
+        }
+
+        graph
+    }
+
+    /// One vertex per capsule; links within a capsule are left out. The
+    /// response code and title of a capsule are the ones of its root page.
+    pub fn hosts(entries: &HashMap<String, UrlInfo>) -> Self {
+        let mut graph = Self::new();
+
+        let mut urls = entries.keys().collect::<Vec<_>>();
+        urls.sort();
+
+        for url in urls {
+            let info = &entries[url];
+            let host = match host_of(url) {
+                Some(h) => h,
+                None => continue,
+            };
+
+            let to = graph.vertex(&host);
+            graph.vertices[to].pages += 1;
+            if Url::parse(url).map(|u| u.path() == "/").unwrap_or(false) {
+                graph.vertices[to].response_code = info.response_code;
+                graph.vertices[to].metatext = info.metatext.clone();
+                graph.vertices[to].title = info.title.clone().unwrap_or_default();
+            }
+
+            for referrer in &info.referred_from {
+                let from = match host_of(referrer) {
+                    Some(h) if h != host => graph.vertex(&h),
+                    _ => continue,
+                };
+                graph.edge(from, to);
+            }
+        }
+
+        graph
+    }
+
+    pub fn write_dot(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "digraph geminispace {{")?;
+
+        for (i, v) in self.vertices.iter().enumerate() {
+            writeln!(out, "    {} [label=\"{}\", status={}, meta=\"{}\", title=\"{}\", pages={}];",
+                i, dot_escape(&v.label), v.response_code,
+                dot_escape(&v.metatext), dot_escape(&v.title), v.pages)?;
+        }
+
+        for ((from, to), weight) in &self.edges {
+            writeln!(out, "    {} -> {} [weight={}];", from, to, weight)?;
+        }
+
+        writeln!(out, "}}")
+    }
+
+    pub fn write_gexf(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(out, "<gexf xmlns=\"http://www.gexf.net/1.2draft\" version=\"1.2\">")?;
+        writeln!(out, "  <graph mode=\"static\" defaultedgetype=\"directed\">")?;
+        writeln!(out, "    <attributes class=\"node\">")?;
+        writeln!(out, "      <attribute id=\"0\" title=\"status\" type=\"integer\"/>")?;
+        writeln!(out, "      <attribute id=\"1\" title=\"meta\" type=\"string\"/>")?;
+        writeln!(out, "      <attribute id=\"2\" title=\"pages\" type=\"integer\"/>")?;
+        writeln!(out, "      <attribute id=\"3\" title=\"title\" type=\"string\"/>")?;
+        writeln!(out, "    </attributes>")?;
+
+        writeln!(out, "    <nodes>")?;
+        for (i, v) in self.vertices.iter().enumerate() {
+            writeln!(out, "      <node id=\"{}\" label=\"{}\">", i, xml_escape(&v.label))?;
+            writeln!(out, "        <attvalues>")?;
+            writeln!(out, "          <attvalue for=\"0\" value=\"{}\"/>", v.response_code)?;
+            writeln!(out, "          <attvalue for=\"1\" value=\"{}\"/>", xml_escape(&v.metatext))?;
+            writeln!(out, "          <attvalue for=\"2\" value=\"{}\"/>", v.pages)?;
+            writeln!(out, "          <attvalue for=\"3\" value=\"{}\"/>", xml_escape(&v.title))?;
+            writeln!(out, "        </attvalues>")?;
+            writeln!(out, "      </node>")?;
+        }
+        writeln!(out, "    </nodes>")?;
+
+        writeln!(out, "    <edges>")?;
+        for (i, ((from, to), weight)) in self.edges.iter().enumerate() {
+            writeln!(out, "      <edge id=\"{}\" source=\"{}\" target=\"{}\" weight=\"{}\"/>",
+                i, from, to, weight)?;
+        }
+        writeln!(out, "    </edges>")?;
+
+        writeln!(out, "  </graph>")?;
+        writeln!(out, "</gexf>")
+    }
+
+    pub fn write_csv(&self, nodes: &mut impl Write, edges: &mut impl Write) -> io::Result<()> {
+        writeln!(nodes, "id,label,status,meta,title,pages")?;
+        for (i, v) in self.vertices.iter().enumerate() {
+            writeln!(nodes, "{},{},{},{},{},{}", i, csv_escape(&v.label),
+                v.response_code, csv_escape(&v.metatext), csv_escape(&v.title), v.pages)?;
+        }
+
+        writeln!(edges, "source,target,weight")?;
+        for ((from, to), weight) in &self.edges {
+            writeln!(edges, "{},{},{}", from, to, weight)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn host_of(url: &str) -> Option<String> {
+    Url::parse(url).ok()?.host_str().map(|h| h.to_string())
+}
+
+/// usage: export <dot|gexf|csv> [--hosts] <results.json> [output]
+pub fn export(args: &[String]) -> Result<(), Box<dyn Error>> {
+    use std::fs::File;
+    use std::io::BufWriter;
+
+    let hosts = args.iter().any(|a| a == "--hosts");
+    let args = args.iter().filter(|a| *a != "--hosts").collect::<Vec<_>>();
+
+    if args.len() < 2 {
+        Err("usage: export <dot|gexf|csv> [--hosts] <results.json> [output]")?;
+    }
+    // before anything gets written over
+    if !["dot", "gexf", "csv"].contains(&args[0].as_str()) {
+        Err(format!("unknown export format '{}'", args[0]))?;
+    }
+
+    let entries = crate::load_data(args[1])?;
+    let graph = if hosts { Graph::hosts(&entries) } else { Graph::pages(&entries) };
+
+    let mut out: Box<dyn Write> = match args.get(2) {
+        Some(path) if args[0] != "csv" => Box::new(BufWriter::new(File::create(path)?)),
+        _ => Box::new(BufWriter::new(io::stdout())),
+    };
+
+    match args[0].as_str() {
+        "dot" => graph.write_dot(&mut out)?,
+        "gexf" => graph.write_gexf(&mut out)?,
+        "csv" => {
+            // two files: <output>-nodes.csv and <output>-edges.csv
+            let prefix = match args.get(2) {
+                Some(p) => p.as_str(),
+                None => "graph",
+            };
+            let mut nodes = BufWriter::new(File::create(format!("{}-nodes.csv", prefix))?);
+            let mut edges = BufWriter::new(File::create(format!("{}-edges.csv", prefix))?);
+            graph.write_csv(&mut nodes, &mut edges)?;
+            println!("wrote {}-nodes.csv and {}-edges.csv", prefix, prefix);
+        },
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> HashMap<String, UrlInfo> {
+        let mut entries = HashMap::new();
+        entries.insert("gemini://a.example:1965/".to_string(), UrlInfo::default());
+        entries.insert("gemini://a.example:1965/x".to_string(),
+            UrlInfo::new("gemini://a.example:1965/".to_string()));
+
+        let mut b = UrlInfo::new("gemini://a.example:1965/".to_string());
+        b.referred_from.push("gemini://a.example:1965/x".to_string());
+        b.response_code = 20;
+        entries.insert("gemini://b.example:1965/".to_string(), b);
+        entries
+    }
+
+    #[test]
+    fn page_graph() {
+        let graph = Graph::pages(&entries());
+        assert_eq!(graph.vertices.len(), 3);
+        assert_eq!(graph.edges.len(), 3);
+    }
+
+    #[test]
+    fn host_graph() {
+        let graph = Graph::hosts(&entries());
+        assert_eq!(graph.vertices.len(), 2);
+        assert_eq!(graph.edges.values().collect::<Vec<_>>(), vec![&2]);
+        assert_eq!(graph.vertices[1].response_code, 20);
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(csv_escape("text/gemini; lang=en"), "text/gemini; lang=en");
+        assert_eq!(csv_escape("a,\"b\""), "\"a,\"\"b\"\"\"");
+        assert_eq!(xml_escape("<a & b>"), "&lt;a &amp; b&gt;");
+        assert_eq!(dot_escape("say \"hi\""), "say \\\"hi\\\"");
+    }
+}
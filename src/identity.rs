@@ -0,0 +1,128 @@
+use rustls::ClientConfig;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use std::error::Error;
+
+use crate::config::IdentityConfig;
+
+/// Client certificates, each for a host and the paths below a prefix.
+pub struct Identities {
+    list: Vec<Identity>,
+}
+
+struct Identity {
+    host: String,
+    path: String,
+    cfg: ClientConfig,
+}
+
+/// What happened when a page asked for a client certificate.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ClientCert {
+    /// the 6x status the page first answered with
+    pub required: usize,
+    pub presented: bool,
+    pub accepted: bool,
+    /// we didn't get a (well-formed) answer with the certificate, so we
+    /// don't know whether it was accepted
+    #[serde(default)]
+    pub failed: bool,
+}
+
+impl Identities {
+    pub fn load(configs: &[IdentityConfig], base: &ClientConfig) -> Result<Self, Box<dyn Error>> {
+        let mut list = Vec::new();
+
+        for c in configs {
+            let (chain, key) = match (&c.cert, &c.key) {
+                (Some(cert), Some(key)) => crate::cert::load_pem(cert, key)?,
+                (None, None) => crate::cert::generate(vec![c.host.clone()])?,
+                _ => Err(format!("identity for {}: cert and key have to be given together", c.host))?,
+            };
+
+            let mut cfg = base.clone();
+            cfg.set_single_client_cert(chain, key)?;
+            list.push(Identity {
+                host: crate::net::ascii_name(&c.host),
+                path: c.path.clone(),
+                cfg,
+            });
+        }
+
+        Ok(Self { list })
+    }
+
+    /// The TLS config with the identity for the url, if we have one. The
+    /// identity with the longest matching path wins.
+    pub fn for_url(&self, url: &Url) -> Option<&ClientConfig> {
+        self.find(url).map(|i| &i.cfg)
+    }
+
+    fn find(&self, url: &Url) -> Option<&Identity> {
+        // as it's written in the url, an IDN is percent-encoded
+        let host = crate::net::Host::of(url).ok()?.to_string();
+
+        self.list.iter()
+            .filter(|i| i.host == host && below(url.path(), &i.path))
+            .max_by_key(|i| i.path.len())
+    }
+}
+
+/// Whether a path is `prefix` or below it, going by whole segments: `/app`
+/// covers `/app/x` but not `/application`.
+fn below(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths() {
+        assert!(below("/app", "/app"));
+        assert!(below("/app/", "/app"));
+        assert!(below("/app/x", "/app"));
+        assert!(!below("/application", "/app"));
+        assert!(below("/app/x", "/app/"));
+        assert!(!below("/app", "/app/"));
+        assert!(below("/anything", "/"));
+        assert!(!below("/other", "/app"));
+    }
+
+    #[test]
+    fn longest_path_wins() {
+        let id = |path: &str| Identity {
+            host: "a.example".to_string(),
+            path: path.to_string(),
+            cfg: ClientConfig::new(),
+        };
+        let identities = Identities { list: vec![id("/"), id("/app")] };
+        let path_for = |url: &str| identities.find(&Url::parse(url).unwrap()).map(|i| i.path.as_str());
+
+        assert_eq!(path_for("gemini://a.example/app/x"), Some("/app"));
+        assert_eq!(path_for("gemini://a.example/application"), Some("/"));
+        assert_eq!(path_for("gemini://b.example/app"), None);
+    }
+
+    #[test]
+    fn idn_hosts() {
+        let id = |host: &str| Identity {
+            host: crate::net::ascii_name(host),
+            path: "/".to_string(),
+            cfg: ClientConfig::new(),
+        };
+        let url = Url::parse("gemini://bücher.example/").unwrap();
+
+        for host in &["bücher.example", "xn--bcher-kva.example", "BÜCHER.example"] {
+            let identities = Identities { list: vec![id(host)] };
+            assert!(identities.find(&url).is_some(), "{}", host);
+        }
+        let identities = Identities { list: vec![id("b.example")] };
+        assert!(identities.find(&url).is_none());
+    }
+}
@@ -0,0 +1,283 @@
+use gemtext::Node;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+
+// BM25 parameters
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+// how much a term counts for depending on where it appears
+const HEADING_WEIGHT: u32 = 3;
+const LINK_WEIGHT: u32 = 2;
+const TEXT_WEIGHT: u32 = 1;
+
+const MAX_TERM_LEN: usize = 64;
+
+// bumped whenever the format on disk changes; the first had no version
+const VERSION: u32 = 2;
+
+/// An inverted index over the text of every crawled text/gemini page.
+#[derive(Deserialize, Serialize)]
+pub struct Index {
+    #[serde(default)]
+    version: u32,
+    docs: Vec<Document>,
+    /// term -> [(document, weighted term frequency, positions)]
+    postings: HashMap<String, Vec<(u32, u32, Vec<u32>)>>,
+    total_length: u64,
+
+    #[serde(skip)]
+    by_url: HashMap<String, u32>,
+    // document -> its terms, so it can be taken out again quickly
+    #[serde(skip)]
+    terms: Vec<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Document {
+    pub url: String,
+    length: u32,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Self {
+            version: VERSION,
+            docs: Vec::new(),
+            postings: HashMap::new(),
+            total_length: 0,
+            by_url: HashMap::new(),
+            terms: Vec::new(),
+        }
+    }
+}
+
+impl Index {
+    /// An index saved with `save`. One in an older format is left behind,
+    /// for an empty one: pages are indexed again when they're fetched again.
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let version = json.get("version").and_then(|v| v.as_u64()).unwrap_or(0);
+        if version != VERSION as u64 {
+            warn!("{} is in an older format (version {}), starting a new index", path, version);
+            return Ok(Self::default());
+        }
+
+        let mut index: Index = serde_json::from_value(json)?;
+        index.by_url = index.docs.iter().enumerate()
+            .map(|(i, d)| (d.url.clone(), i as u32))
+            .collect();
+        index.terms = vec![Vec::new(); index.docs.len()];
+        for (term, list) in &index.postings {
+            for (doc, _, _) in list {
+                index.terms[*doc as usize].push(term.clone());
+            }
+        }
+        Ok(index)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        crate::write_file(path, serde_json::to_string(&self)?.as_bytes())
+    }
+
+    pub fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    pub fn url(&self, doc: u32) -> &str {
+        &self.docs[doc as usize].url
+    }
+
+    pub fn doc(&self, url: &str) -> Option<u32> {
+        self.by_url.get(url).copied()
+    }
+
+    /// Index a page, replacing whatever was indexed for it before.
+    pub fn add(&mut self, url: &str, nodes: &[Node]) {
+        let mut terms: HashMap<String, (u32, Vec<u32>)> = HashMap::new();
+        let mut position = 0;
+
+        for node in nodes {
+            let (text, weight) = match node {
+                Node::Heading { body, .. } => (body.as_str(), HEADING_WEIGHT),
+                Node::Link { name: Some(name), .. } => (name.as_str(), LINK_WEIGHT),
+                Node::Link { name: None, .. } => continue,
+                Node::Text(body) | Node::ListItem(body)
+                    | Node::Quote(body) | Node::Preformatted(body) => (body.as_str(), TEXT_WEIGHT),
+            };
+
+            for term in tokenize(text) {
+                let (tf, positions) = terms.entry(term).or_default();
+                *tf += weight;
+                positions.push(position);
+                position += 1;
+            }
+        }
+
+        let length = terms.values().map(|(tf, _)| tf).sum::<u32>();
+
+        let doc = match self.by_url.get(url) {
+            Some(&doc) => {
+                self.remove(doc);
+                self.docs[doc as usize].length = length;
+                doc
+            },
+            None => {
+                self.docs.push(Document { url: url.to_string(), length });
+                self.terms.push(Vec::new());
+                let doc = self.docs.len() as u32 - 1;
+                self.by_url.insert(url.to_string(), doc);
+                doc
+            },
+        };
+        self.total_length += length as u64;
+
+        self.terms[doc as usize] = terms.keys().cloned().collect();
+        for (term, (tf, positions)) in terms {
+            self.postings.entry(term).or_default().push((doc, tf, positions));
+        }
+    }
+
+    fn remove(&mut self, doc: u32) {
+        self.total_length -= self.docs[doc as usize].length as u64;
+        for term in std::mem::take(&mut self.terms[doc as usize]) {
+            if let Some(list) = self.postings.get_mut(&term) {
+                list.retain(|(d, _, _)| *d != doc);
+                if list.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    /// Score every document containing at least one of the terms with BM25.
+    pub fn score(&self, terms: &[String]) -> HashMap<u32, f64> {
+        let mut scores: HashMap<u32, f64> = HashMap::new();
+
+        for term in terms {
+            for (doc, score) in self.term_scores(term) {
+                *scores.entry(doc).or_insert(0.0) += score;
+            }
+        }
+
+        scores
+    }
+
+    pub fn containing(&self, term: &str) -> HashSet<u32> {
+        match self.postings.get(term) {
+            Some(list) => list.iter().map(|(doc, _, _)| *doc).collect(),
+            None => HashSet::new(),
+        }
+    }
+
+    /// Documents in which the terms appear right after each other.
+    pub fn containing_phrase(&self, terms: &[String]) -> HashSet<u32> {
+        let lists = terms.iter()
+            .map(|t| self.postings.get(t)
+                .map(|list| list.iter().map(|(d, _, p)| (*d, p)).collect::<HashMap<_, _>>()))
+            .collect::<Option<Vec<_>>>();
+        let lists = match lists {
+            Some(l) if !l.is_empty() => l,
+            _ => return HashSet::new(),
+        };
+
+        lists[0].iter()
+            .filter(|(doc, first)| first.iter().any(|&start| {
+                lists[1..].iter().enumerate().all(|(i, list)| match list.get(doc) {
+                    Some(positions) => positions.binary_search(&(start + i as u32 + 1)).is_ok(),
+                    None => false,
+                })
+            }))
+            .map(|(doc, _)| *doc)
+            .collect()
+    }
+
+    fn term_scores(&self, term: &str) -> Vec<(u32, f64)> {
+        let list = match self.postings.get(term) {
+            Some(l) => l,
+            None => return Vec::new(),
+        };
+
+        let n = self.docs.len() as f64;
+        let df = list.len() as f64;
+        let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+        let avgdl = (self.total_length as f64 / n).max(1.0);
+
+        list.iter().map(|&(doc, tf, _)| {
+            let tf = tf as f64;
+            let dl = self.docs[doc as usize].length as f64;
+            let score = idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * dl / avgdl));
+            (doc, score)
+        }).collect()
+    }
+}
+
+/// Split text into lowercase alphanumeric terms.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty() && t.len() <= MAX_TERM_LEN)
+        .map(|t| t.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bm25() {
+        let mut index = Index::default();
+        index.add("gemini://a/", &gemtext::parse("# Rust\nsome text about rust and gemini"));
+        index.add("gemini://b/", &gemtext::parse("gemini gemini gemini\nnothing else"));
+        index.add("gemini://c/", &gemtext::parse("=> /x Rust links"));
+
+        let scores = index.score(&["rust".to_string()]);
+        assert_eq!(scores.len(), 2);
+        assert!(scores[&0] > scores[&2]);
+
+        assert_eq!(index.containing_phrase(&["about".to_string(), "rust".to_string()]),
+            vec![0].into_iter().collect());
+        assert!(index.containing_phrase(&["rust".to_string(), "about".to_string()]).is_empty());
+
+        // re-indexing a page replaces it
+        index.add("gemini://a/", &gemtext::parse("nothing here anymore"));
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.score(&["rust".to_string()]).len(), 1);
+        assert!(!index.postings.contains_key("about"));
+        assert_eq!(index.containing("gemini"), vec![1].into_iter().collect());
+        assert_eq!(index.total_length, 3 + 5 + 2 * 2);
+    }
+
+    #[test]
+    fn formats() {
+        let mut index = Index::default();
+        index.add("gemini://a/", &gemtext::parse("some text"));
+
+        let path = std::env::temp_dir().join(format!("gc-index-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        index.save(path).unwrap();
+        let mut loaded = Index::load(path).unwrap();
+        assert_eq!((loaded.len(), loaded.doc("gemini://a/")), (1, Some(0)));
+
+        // what was loaded can be replaced too
+        loaded.add("gemini://a/", &gemtext::parse("other words"));
+        assert!(loaded.containing("text").is_empty());
+        assert_eq!(loaded.containing("words").len(), 1);
+
+        // the first format, without positions, is left behind
+        fs::write(path, r#"{"docs":[{"url":"gemini://a/","length":1}],"postings":{"text":[[0,1]]},"total_length":1}"#)
+            .unwrap();
+        assert_eq!(Index::load(path).unwrap().len(), 0);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(tokenize("Hello, Wörld! foo_bar 42"),
+            vec!["hello", "wörld", "foo", "bar", "42"]);
+    }
+}
@@ -0,0 +1,134 @@
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use std::collections::HashMap;
+
+use crate::UrlInfo;
+
+/// A page that answered with 10 (input) or 11 (sensitive input).
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct InputEndpoint {
+    pub kind: InputKind,
+    pub prompt: String,
+    pub sensitive: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputKind {
+    Search,
+    Guestbook,
+    Form,
+}
+
+const SEARCH_WORDS: &[&str] = &["search", "query", "find", "look up", "lookup", "keyword", "keywords"];
+const GUESTBOOK_WORDS: &[&str] = &["guestbook", "guest book", "comment", "comments", "sign",
+    "message", "messages", "reply"];
+
+impl InputEndpoint {
+    pub fn new(url: &Url, prompt: &str, sensitive: bool) -> Self {
+        Self {
+            kind: classify(url, prompt),
+            prompt: prompt.to_string(),
+            sensitive,
+        }
+    }
+}
+
+/// Guess what an input endpoint is for from its path and prompt.
+pub fn classify(url: &Url, prompt: &str) -> InputKind {
+    // whole words only: /design isn't for signing
+    let words = crate::index::tokenize(&format!("{} {}", url.path(), prompt));
+    let haystack = format!(" {} ", words.join(" "));
+    let has = |word: &&str| haystack.contains(&format!(" {} ", word));
+
+    if SEARCH_WORDS.iter().any(has) {
+        InputKind::Search
+    } else if GUESTBOOK_WORDS.iter().any(has) {
+        InputKind::Guestbook
+    } else {
+        InputKind::Form
+    }
+}
+
+/// Whether we may fetch a url, given what it would submit as input.
+#[derive(Debug, PartialEq)]
+pub enum Submit {
+    /// there's no input, or the endpoint takes ordinary input
+    Fine,
+    /// the endpoint asked for sensitive input (status 11), which we never send
+    Sensitive,
+    /// we haven't heard from the endpoint (this url without its query) yet,
+    /// so ask it first
+    Unknown(Url),
+}
+
+/// What fetching this url would mean for the input endpoint it submits to.
+pub fn check(entries: &HashMap<String, UrlInfo>, url: &Url) -> Submit {
+    if url.scheme() != "gemini" || url.query().is_none() {
+        return Submit::Fine;
+    }
+
+    let mut endpoint = url.clone();
+    endpoint.set_query(None);
+
+    match entries.get(endpoint.as_str()) {
+        Some(info) if info.response_code != 0 => match &info.input {
+            Some(input) if input.sensitive => Submit::Sensitive,
+            _ => Submit::Fine,
+        },
+        _ => Submit::Unknown(endpoint),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kinds() {
+        let url = |u: &str| Url::parse(u).unwrap();
+        assert_eq!(classify(&url("gemini://a/cgi-bin/s"), "Enter search terms"), InputKind::Search);
+        assert_eq!(classify(&url("gemini://a/guestbook"), "Your name?"), InputKind::Guestbook);
+        assert_eq!(classify(&url("gemini://a/login"), "Password"), InputKind::Form);
+        assert_eq!(classify(&url("gemini://a/guest-book"), "Leave a Comment"), InputKind::Guestbook);
+        assert_eq!(classify(&url("gemini://a/look/up"), "Word?"), InputKind::Search);
+
+        // not words of their own
+        assert_eq!(classify(&url("gemini://a/signin"), "Name"), InputKind::Form);
+        assert_eq!(classify(&url("gemini://a/design"), "Colour"), InputKind::Form);
+        assert_eq!(classify(&url("gemini://a/tasks"), "Assign to whom?"), InputKind::Form);
+        assert_eq!(classify(&url("gemini://a/research"), "Topic"), InputKind::Form);
+    }
+
+    #[test]
+    fn sensitive() {
+        let url = |u: &str| Url::parse(u).unwrap();
+        let mut entries = HashMap::new();
+        let endpoint = url("gemini://a:1965/login");
+        let login = UrlInfo {
+            response_code: 11,
+            input: Some(InputEndpoint::new(&endpoint, "Password", true)),
+            ..Default::default()
+        };
+        entries.insert(endpoint.to_string(), login);
+        let page = UrlInfo { response_code: 20, ..Default::default() };
+        entries.insert("gemini://a:1965/page".to_string(), page);
+
+        assert_eq!(check(&entries, &url("gemini://a:1965/login?hunter2")), Submit::Sensitive);
+        assert_eq!(check(&entries, &endpoint), Submit::Fine);
+        assert_eq!(check(&entries, &url("gemini://a:1965/page?x")), Submit::Fine);
+    }
+
+    #[test]
+    fn endpoint_unknown() {
+        let url = |u: &str| Url::parse(u).unwrap();
+        let mut entries = HashMap::new();
+
+        // never seen, or seen but not answered yet: ask the endpoint first
+        let unknown = Submit::Unknown(url("gemini://a:1965/login"));
+        assert_eq!(check(&entries, &url("gemini://a:1965/login?hunter2")), unknown);
+        entries.insert("gemini://a:1965/login".to_string(), UrlInfo::default());
+        assert_eq!(check(&entries, &url("gemini://a:1965/login?hunter2")), unknown);
+    }
+}
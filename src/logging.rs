@@ -0,0 +1,131 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::LogConfig;
+
+/// Log records go to stderr as text and, if configured, to a file as JSON
+/// lines. Each module can have its own level.
+struct Logger {
+    settings: RwLock<Settings>,
+}
+
+struct Settings {
+    level: LevelFilter,
+    // module -> level, longest module first
+    modules: Vec<(String, LevelFilter)>,
+    file: Option<Mutex<File>>,
+}
+
+static LOGGER: Logger = Logger {
+    settings: RwLock::new(Settings { level: LevelFilter::Info, modules: Vec::new(), file: None }),
+};
+
+#[derive(Serialize)]
+struct Line<'a> {
+    ts: u128,
+    level: String,
+    module: &'a str,
+    message: String,
+}
+
+/// Start logging at info level, until `configure`d otherwise.
+pub fn init() -> Result<(), Box<dyn Error>> {
+    log::set_logger(&LOGGER)?;
+    log::set_max_level(LevelFilter::Info);
+    Ok(())
+}
+
+pub fn configure(cfg: &LogConfig) -> Result<(), Box<dyn Error>> {
+    let mut modules = cfg.modules.iter()
+        .map(|(m, l)| (m.clone(), *l))
+        .collect::<Vec<_>>();
+    modules.sort_by_key(|(m, _)| std::cmp::Reverse(m.len()));
+
+    let file = match &cfg.file {
+        Some(path) => Some(Mutex::new(OpenOptions::new().create(true).append(true).open(path)?)),
+        None => None,
+    };
+
+    let max = modules.iter().map(|(_, l)| *l).fold(cfg.level, std::cmp::max);
+    *LOGGER.settings.write().unwrap() = Settings { level: cfg.level, modules, file };
+    log::set_max_level(max);
+    Ok(())
+}
+
+impl Settings {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        // our own modules can be named without the crate
+        let target = target.strip_prefix("gc::").unwrap_or(target);
+
+        self.modules.iter()
+            .find(|(m, _)| target == m || target.starts_with(&format!("{}::", m)))
+            .map(|(_, l)| *l)
+            .unwrap_or(self.level)
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.settings.read().unwrap().level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        let settings = self.settings.read().unwrap();
+        if record.level() > settings.level_for(record.target()) {
+            return;
+        }
+
+        crate::progress::clear();
+        eprintln!("{:<5} {}: {}", record.level(), record.target(), record.args());
+
+        if let Some(file) = &settings.file {
+            let line = Line {
+                ts: SystemTime::now().duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_millis())
+                    .unwrap_or(0),
+                level: record.level().to_string(),
+                module: record.target(),
+                message: record.args().to_string(),
+            };
+            if let Ok(json) = serde_json::to_string(&line) {
+                let _ = writeln!(file.lock().unwrap(), "{}", json);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.settings.read().unwrap().file {
+            let _ = file.lock().unwrap().flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels() {
+        let settings = Settings {
+            level: LevelFilter::Info,
+            modules: vec![
+                ("net::socks".to_string(), LevelFilter::Trace),
+                ("net".to_string(), LevelFilter::Debug),
+                ("rustls".to_string(), LevelFilter::Off),
+            ],
+            file: None,
+        };
+
+        assert_eq!(settings.level_for("gc"), LevelFilter::Info);
+        assert_eq!(settings.level_for("gc::net"), LevelFilter::Debug);
+        assert_eq!(settings.level_for("gc::net::socks"), LevelFilter::Trace);
+        assert_eq!(settings.level_for("gc::network"), LevelFilter::Info);
+        assert_eq!(settings.level_for("rustls::client"), LevelFilter::Off);
+    }
+}
@@ -1,28 +1,111 @@
+use log::{debug, info, warn};
 use url::{Url, ParseError};
-use tokio::net::TcpStream;
 use tokio_rustls::TlsConnector;
 use gemtext::*;
 use serde::{Deserialize, Serialize};
 
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::error::Error;
 use std::collections::HashMap;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::fs;
+use std::net::IpAddr;
+
+use error::{ErrorClass, FetchError};
+
+mod breaker;
+mod cert;
+mod config;
+mod error;
+mod extract;
+mod frontier;
+mod gopher;
+mod graph;
+mod identity;
+mod index;
+mod input;
+mod logging;
+mod metrics;
+mod mime;
+mod net;
+mod progress;
+mod query;
+mod rank;
+mod report;
+mod retry;
+mod serve;
+mod shutdown;
+mod socks;
 
 const TIMEOUT_MS: u64 = 5000;
 const SAVEFREQ: usize = 1000;
 
-const START_URL: &'static str = "gemini://gemini.circumlunar.space:1965/";
-const OUTFILE: &'static str = "results.json";
+const START_URL: &str = "gemini://gemini.circumlunar.space:1965/";
+const OUTFILE: &str = "results.json";
+const INDEXFILE: &str = "index.json";
+const FRONTIERFILE: &str = "frontier.json";
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+// how long fetches may go on after we're asked to stop
+const SHUTDOWN_GRACE_MS: u64 = 10000;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 struct UrlInfo {
     referred_from: Vec<String>,
     timed_out: bool,
     malformed_response: bool,
     response_code: usize,
     metatext: String,
+    #[serde(default)]
+    external_links: Vec<ExternalLink>,
+    #[serde(default)]
+    rank: Option<rank::Rank>,
+    // unix time of the last fetch attempt
+    #[serde(default)]
+    fetched_at: Option<u64>,
+    // unix time of when we first came across it
+    #[serde(default)]
+    discovered_at: Option<u64>,
+    #[serde(default)]
+    bytes: usize,
+    // for text/gemini pages only
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    outline: Vec<(u8, String)>,
+    #[serde(default)]
+    links: usize,
+    #[serde(default)]
+    words: usize,
+    // as declared in the meta of a 2x response
+    #[serde(default)]
+    lang: Option<String>,
+    // for 10 and 11 responses
+    #[serde(default)]
+    input: Option<input::InputEndpoint>,
+    // for 6x responses
+    #[serde(default)]
+    client_cert: Option<identity::ClientCert>,
+    // for gopher urls
+    #[serde(default)]
+    item_type: Option<char>,
+    // why we couldn't get a (well-formed) response
+    #[serde(default)]
+    error: Option<FetchError>,
+    // what the host resolved to when we fetched this
+    #[serde(default)]
+    addresses: Vec<IpAddr>,
+    // every time we tried to fetch this
+    #[serde(default)]
+    attempts: Vec<retry::Attempt>,
+}
+
+// a link to something outside of geminispace (http, gopher, mailto, ...)
+// that we record but never fetch
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct ExternalLink {
+    scheme: String,
+    target: String,
+    name: Option<String>,
 }
 
 impl UrlInfo {
@@ -33,21 +116,102 @@ impl UrlInfo {
             malformed_response: false,
             response_code: 0,
             metatext: "".to_string(),
+            external_links: Vec::new(),
+            rank: None,
+            fetched_at: None,
+            discovered_at: Some(unix_time()),
+            bytes: 0,
+            title: None,
+            outline: Vec::new(),
+            links: 0,
+            words: 0,
+            lang: None,
+            input: None,
+            client_cert: None,
+            item_type: None,
+            error: None,
+            addresses: Vec::new(),
+            attempts: Vec::new(),
         }
     }
+
+    /// Whether we got a response: with any status for gemini, or at all for
+    /// gopher, which has no status codes.
+    fn answered(&self) -> bool {
+        match self.item_type {
+            Some(_) => self.fetched_at.is_some() && !self.timed_out && self.error.is_none(),
+            None => self.response_code != 0,
+        }
+    }
+
+    /// Forget how the last try went, before trying again.
+    fn begin_fetch(&mut self) {
+        self.fetched_at = Some(unix_time());
+        self.timed_out = false;
+        self.malformed_response = false;
+        self.response_code = 0;
+        self.error = None;
+    }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = std::env::args().collect::<Vec<_>>();
-    let mut entries = HashMap::new();
+    logging::init()?;
+
+    match args.get(1).map(|a| a.as_str()) {
+        Some("export") => graph::export(&args[2..]),
+        Some("rank") => rank::list(&args[2..]),
+        Some("report") => report::capsules(&args[2..]),
+        Some("broken") => report::broken(&args[2..]),
+        Some("clusters") => report::list_clusters(&args[2..]),
+        Some("search") => query::search(&args[2..]),
+        Some("serve") => serve::serve(&args[2..]),
+        _ => run_crawl(&args[1..]),
+    }
+}
 
-    if args.len() > 1 {
-        eprint!("Reading from {}... ", &args[1]);
-        let json = fs::read_to_string(&args[1])?;
+/// usage: [--config config.json] [results.json]
+fn run_crawl(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut entries = HashMap::new();
+    let mut index = index::Index::default();
+    let mut config = config::Config::default();
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => config = config::Config::load(args.next().ok_or("--config needs a path")?)?,
+            _ => paths.push(arg),
+        }
+    }
 
-        eprint!("done\nDeserializing JSON data... ");
-        entries = serde_json::from_str(&json)?;
-        eprint!("done\n");
+    logging::configure(&config.log)?;
+
+    let mut frontier = frontier::Frontier::default();
+    let files = DataFiles::next_to(paths.first().map(|p| p.as_str()).unwrap_or(OUTFILE));
+    if !paths.is_empty() {
+        entries = load_data(&files.results)?;
+
+        // every url in the index and the frontier has an entry, unless
+        // they were left there by a crawl of something else
+        if std::path::Path::new(&files.index).exists() {
+            let loaded = index::Index::load(&files.index)?;
+            if (0..loaded.len() as u32).all(|doc| entries.contains_key(loaded.url(doc))) {
+                index = loaded;
+            } else {
+                warn!("{} doesn't go with {}, starting a new index", files.index, files.results);
+            }
+        }
+        // pick up where an interrupted crawl left off
+        if std::path::Path::new(&files.frontier).exists() {
+            let loaded = frontier::Frontier::load(&files.frontier)?;
+            if loaded.urls().all(|url| entries.contains_key(url.as_str())) {
+                frontier = loaded;
+                info!("resuming with {} urls from {}", frontier.len(), files.frontier);
+            } else {
+                warn!("{} doesn't go with {}, not resuming from it", files.frontier, files.results);
+            }
+        }
     }
 
     let mut cfg = tokio_rustls::rustls::ClientConfig::new();
@@ -55,214 +219,599 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .dangerous()
         .set_certificate_verifier(Arc::new(NoCertificateVerification {}));
 
+    let fetcher = Fetcher {
+        identities: identity::Identities::load(&config.identities, &cfg)?,
+        cfg,
+        resolver: net::Resolver::new(&config.net),
+        timeout: Duration::from_millis(TIMEOUT_MS),
+    };
 
-    smol::run(crawl(entries, START_URL, cfg))?;
+    smol::run(crawl(entries, index, frontier, &files, START_URL, fetcher, config))?;
     Ok(())
 }
 
-async fn crawl(mut entries: HashMap<String, UrlInfo>, start: &str,
-    cfg: tokio_rustls::rustls::ClientConfig) -> Result<(), Box<dyn Error>>
-{
-    use tokio::time::timeout;
-    let duration = Duration::from_millis(TIMEOUT_MS);
+/// Where a crawl keeps its data: the results, and the index and frontier
+/// next to them.
+struct DataFiles {
+    results: String,
+    index: String,
+    frontier: String,
+}
 
-    let start = parse_url(None, start)?;
+impl DataFiles {
+    fn next_to(results: &str) -> Self {
+        let path = std::path::Path::new(results);
+        let sibling = |name| path.with_file_name(name).to_string_lossy().into_owned();
+        Self {
+            results: results.to_string(),
+            index: sibling(INDEXFILE),
+            frontier: sibling(FRONTIERFILE),
+        }
+    }
+}
 
-    // queue to visit
-    let mut queue: Vec<Url> = Vec::new();
+/// What we need to fetch pages, besides the urls.
+struct Fetcher {
+    cfg: tokio_rustls::rustls::ClientConfig,
+    identities: identity::Identities,
+    resolver: net::Resolver,
+    timeout: Duration,
+}
 
-    // start crawling with the first url
-    let response = get(&start, cfg.clone()).await?;
-    let urls = extract_urls(&start, response);
+async fn crawl(mut entries: HashMap<String, UrlInfo>, mut index: index::Index,
+    mut frontier: frontier::Frontier, files: &DataFiles, start: &str, fetcher: Fetcher,
+    config: config::Config)
+    -> Result<(), Box<dyn Error>>
+{
+    let start = parse_url(None, start)?;
+    let shutdown = shutdown::listen()?;
+    let grace = Duration::from_millis(SHUTDOWN_GRACE_MS);
+    let retry = retry::Policy::new(config.retry);
+    let mut breakers = breaker::Breakers::new(config.breaker);
+    let gopher_cfg = config.gopher;
+
+    let metrics = Arc::new(Mutex::new(metrics::Metrics::default()));
+    if let Some(listen) = &config.metrics.listen {
+        metrics::spawn(listen, metrics.clone()).await?;
+    }
 
-    for url in &urls {
-        queue.push(url.clone());
-        entries.insert(url.to_string(), UrlInfo::new(start.to_string()));
+    // start crawling with the first url, unless we're resuming
+    if frontier.is_empty() {
+        entries.entry(start.to_string()).or_insert_with(|| UrlInfo {
+            discovered_at: Some(unix_time()),
+            ..Default::default()
+        });
+        frontier.push(start);
     }
 
     // main crawl
+    let progress = Arc::new(Mutex::new(progress::Progress::new()));
+    let ticker = progress::spawn(progress.clone());
     let mut savectr = 0;
-    while queue.len() > 0 {
+    let mut gopher_pages = 0;
+    while !frontier.is_empty() && !shutdown.requested() {
         savectr += 1;
         if savectr == SAVEFREQ {
-            save_data(&mut entries)?;
+            checkpoint(files, &entries, &index, &frontier)?;
             savectr = 0;
         }
 
-        status(queue.len(), entries.len(), 0);
-
-        // move on to the next link
-        let link = queue.pop().unwrap();
-        let link_str = &link.to_string();
-        let mut link_info = entries.get_mut(link_str).unwrap();
-
-        // get gemini text
-        let response = match timeout(duration, get(&link, cfg.clone())).await {
-            Ok(result) => match result {
-                Ok(o) => o,
-                Err(e) => {
-                    eprintln!("\nfailed to fetch {}: {}", link.to_string(), e);
-                    continue;
-                },
-            },
-            Err(_) => {
-                link_info.timed_out = true;
+        progress.lock().unwrap().set_sizes(&frontier, entries.len());
+        metrics.lock().unwrap().set_sizes(frontier.len(), entries.len());
+
+        // move on to the next link, or wait for one we're retrying
+        let link = match frontier.pop() {
+            Some(link) => link,
+            None => {
+                tokio::select! {
+                    _ = tokio::time::delay_for(frontier.wait().unwrap_or_default()) => (),
+                    _ = shutdown.wait() => (),
+                }
                 continue;
             },
         };
 
-        if response.len() == 0 {
+        if !entries.contains_key(link.as_str()) {
+            warn!("{} is queued but has no entry, skipping it", link);
             continue;
         }
 
-        let response_str = match std::str::from_utf8(&response) {
-            Ok(s) => s,
-            Err(_) => { link_info.malformed_response = true; continue; },
+        let link = match input::check(&entries, &link) {
+            input::Submit::Fine => link,
+            input::Submit::Sensitive => continue,
+            // find out what the endpoint asks for before sending it anything
+            input::Submit::Unknown(endpoint) => {
+                // no page links to it, so like the start url it has no referrers
+                if !entries.contains_key(endpoint.as_str()) {
+                    entries.insert(endpoint.to_string(), UrlInfo {
+                        discovered_at: Some(unix_time()),
+                        ..Default::default()
+                    });
+                    frontier.push(endpoint.clone());
+                }
+                frontier.wait_for(&endpoint, link);
+                continue;
+            },
+        };
+
+        // leave hosts that keep failing alone for a while
+        let host = link.host_str().unwrap_or("").to_lowercase();
+        match breakers.check(&host) {
+            breaker::Verdict::Go => (),
+            breaker::Verdict::Wait(wait) => {
+                frontier.defer(link, wait);
+                continue;
+            },
+            breaker::Verdict::Skip => continue,
+        }
+
+        // retries don't count as more pages
+        if link.scheme() == "gopher" && entries[link.as_str()].fetched_at.is_none() {
+            if gopher_pages >= gopher_cfg.max_pages {
+                continue;
+            }
+            gopher_pages += 1;
+        }
+
+        let fetch_start = Instant::now();
+        progress.lock().unwrap().started(&link);
+        // how the entry was, in case we have to give up on this fetch
+        let before = entries[link.as_str()].clone();
+        let fetch = async {
+            if link.scheme() == "gopher" {
+                crawl_gopher(&mut entries, &mut frontier, &link, &fetcher).await;
+            } else {
+                crawl_gemini(&mut entries, &mut index, &mut frontier, &link, &fetcher,
+                    gopher_cfg.enabled).await;
+            }
+        };
+        // when we're stopping, don't wait for the fetch forever
+        let interrupted = tokio::select! {
+            _ = fetch => false,
+            _ = shutdown.deadline(grace) => true,
         };
 
-        let header;
-        if let Some(h) = response_str.split("\n").next() {
-            header = h;
+        if interrupted {
+            // as if we'd never started; it's fetched when we resume
+            entries.insert(link.to_string(), before);
+            progress.lock().unwrap().cancelled();
+            frontier.push(link);
+            break;
+        }
+
+        let link_info = entries.get_mut(link.as_str()).unwrap();
+
+        // keep track of how it went, and try again later if it's worth it
+        let failed = link_info.timed_out || link_info.error.is_some();
+        if link_info.fetched_at.is_none() {
+            progress.lock().unwrap().cancelled();
         } else {
-            link_info.malformed_response = true;
-            continue;
+            progress.lock().unwrap().finished(link_info.bytes, failed);
+            frontier.fetched(&link);
+            metrics.lock().unwrap().fetched(link_info, fetch_start.elapsed());
+            breakers.record(&host, failed);
+            link_info.attempts.push(retry::Attempt::of(link_info));
+            if let Some(delay) = retry.retry_after(link_info) {
+                debug!("retrying {} in {:?}", link, delay);
+                frontier.defer(link, delay);
+            }
         }
+    }
 
-        let response_code_str = header[0..=1].to_string();
-        let response_code = match response_code_str.parse::<usize>() {
-            Ok(r) => r,
-            Err(_) => { link_info.malformed_response = true; continue; },
+    ticker.cancel().await;
+    rank::rank_entries(&mut entries);
+    checkpoint(files, &entries, &index, &frontier)?;
+
+    summary(&entries, &breakers);
+    if !frontier.is_empty() {
+        println!("stopped with {} urls left; run again with {} to resume", frontier.len(), files.results);
+    }
+    Ok(())
+}
+
+fn summary(entries: &HashMap<String, UrlInfo>, breakers: &breaker::Breakers) {
+    progress::clear();
+
+    let fetched = entries.values().filter(|i| i.fetched_at.is_some()).count();
+    let ok = entries.values().filter(|i| i.answered()).count();
+    let timeouts = entries.values().filter(|i| i.timed_out).count();
+
+    println!("{} entries, {} fetched, {} answered, {} timed out",
+        entries.len(), fetched, ok, timeouts);
+    for (class, n) in error::counts(entries) {
+        println!("{:>8} {}", n, class);
+    }
+
+    let tripped = breakers.tripped();
+    if !tripped.is_empty() {
+        println!("{} hosts kept failing:", tripped.len());
+    }
+    for (host, t) in tripped {
+        let state = if t.closed { "recovered" } else { "gave up" };
+        println!("{:>8} {} (tripped {} times, {} urls skipped)", state, host, t.trips, t.skipped);
+    }
+}
+
+async fn crawl_gemini(
+    entries: &mut HashMap<String, UrlInfo>,
+    index: &mut index::Index,
+    frontier: &mut frontier::Frontier,
+    link: &Url,
+    fetcher: &Fetcher,
+    follow_gopher: bool
+) {
+    use tokio::time::timeout;
+
+    let link_info = entries.get_mut(link.as_str()).unwrap();
+    link_info.begin_fetch();
+
+    // get gemini text
+    let result = timeout(fetcher.timeout, get(link, fetcher.cfg.clone(), &fetcher.resolver)).await;
+    link_info.addresses = fetcher.resolver.addresses(link);
+    let mut response = match result {
+        Ok(result) => match result {
+            Ok(o) => o,
+            Err(e) => {
+                warn!("failed to fetch {}: {}", link, e);
+                link_info.error = Some(e);
+                return;
+            },
+        },
+        Err(_) => {
+            link_info.timed_out = true;
+            return;
+        },
+    };
+
+    let (mut response_code, mut metatext, mut body_start) = match parse_header(&response) {
+        Some(h) => h,
+        None => {
+            let message = if response.is_empty() {
+                "empty response".to_string()
+            } else {
+                let start = &response[..response.len().min(64)];
+                format!("bad header: {:?}", String::from_utf8_lossy(start))
+            };
+            link_info.malformed_response = true;
+            link_info.error = Some(FetchError::new(ErrorClass::MalformedHeader, message));
+            return;
+        },
+    };
+
+    // present a client certificate if we have one for this area
+    if response_code / 10 == 6 {
+        let mut cert = identity::ClientCert {
+            required: response_code,
+            presented: false,
+            accepted: false,
+            failed: false,
         };
-        let metatext = header[3..].to_string();
-
-        link_info.response_code = response_code;
-        link_info.metatext = metatext.clone();
-
-        match response_code {
-            10 => (), // input required
-            11 => (), // sensitive input required
-            // 20 success
-            20 => {
-                if metatext.starts_with("text/gemini") {
-                    handle_gemtext(&mut entries, &mut queue, &link, response);
+
+        let id_cfg = match response_code {
+            60 => fetcher.identities.for_url(link),
+            _ => None,
+        };
+        if let Some(id_cfg) = id_cfg {
+            cert.presented = true;
+            // until we hear back
+            cert.failed = true;
+            if let Ok(Ok(r)) = timeout(fetcher.timeout, get(link, id_cfg.clone(), &fetcher.resolver)).await {
+                if let Some(h) = parse_header(&r) {
+                    cert.failed = false;
+                    cert.accepted = h.0 / 10 != 6;
+                    response = r;
+                    response_code = h.0;
+                    metatext = h.1;
+                    body_start = h.2;
                 }
-            },
-            30 => (), // temporary redirect
-            31 => (), // permanent redirect
-            40 => (), // temporary failure
-            41 => (), // server unavailable (load or maintainance)
-            42 => (), // cgi/cms error
-            43 => (), // proxy error
-            44 => (), // slow down (ratelimited)
-            50 => (), // permanent failure
-            51 => (), // not found
-            52 => (), // gone (removed permanently)
-            53 => (), // proxy request refused
-            59 => (), // malformed request
-            60 => (), // client cert required
-            61 => (), // unauthorised client cert used
-            62 => (), // invalid client cert used
-            _ => (),  // ???
+            }
         }
+
+        link_info.client_cert = Some(cert);
     }
 
-    save_data(&mut entries)?;
-    Ok(())
+    link_info.bytes = response.len();
+    let body = &response[body_start..];
+
+    link_info.response_code = response_code;
+    link_info.metatext = metatext.clone();
+
+    match response_code {
+        // input required, sensitive input required
+        10 | 11 => {
+            link_info.input = Some(input::InputEndpoint::new(link,
+                &metatext, response_code == 11));
+        },
+        // 20 success
+        20 => {
+            let mime = mime::Mime::parse(&metatext);
+            link_info.lang = mime.lang().map(|l| l.to_string());
+
+            if mime.essence == "text/gemini" {
+                let text = mime::decode(body, mime.charset());
+                handle_gemtext(entries, index, frontier, link, &text,
+                    follow_gopher);
+            } else if mime.is_text() {
+                let text = mime::decode(body, mime.charset());
+                if let Some(links) = extract::links(&mime.essence, &text) {
+                    handle_links(entries, frontier, link, links, follow_gopher);
+                }
+            }
+        },
+        30 => (), // temporary redirect
+        31 => (), // permanent redirect
+        40 => (), // temporary failure
+        41 => (), // server unavailable (load or maintainance)
+        42 => (), // cgi/cms error
+        43 => (), // proxy error
+        44 => (), // slow down (ratelimited)
+        50 => (), // permanent failure
+        51 => (), // not found
+        52 => (), // gone (removed permanently)
+        53 => (), // proxy request refused
+        59 => (), // malformed request
+        60 => (), // client cert required
+        61 => (), // unauthorised client cert used
+        62 => (), // invalid client cert used
+        _ => (),  // ???
+    }
+}
+
+/// Split a response into its status code, meta, and the offset of the
+/// body. The header is always utf-8; the body is whatever the meta says.
+fn parse_header(response: &[u8]) -> Option<(usize, String, usize)> {
+    let header_end = response.iter().position(|&b| b == b'\n')
+        .unwrap_or(response.len());
+    let header = std::str::from_utf8(&response[..header_end]).ok()?
+        .trim_end_matches('\r');
+
+    let response_code = header.get(0..2)?.parse::<usize>().ok()?;
+    let metatext = header.get(3..).unwrap_or("").to_string();
+
+    Some((response_code, metatext, (header_end + 1).min(response.len())))
+}
+
+async fn crawl_gopher(
+    entries: &mut HashMap<String, UrlInfo>,
+    frontier: &mut frontier::Frontier,
+    link: &Url,
+    fetcher: &Fetcher
+) {
+    use tokio::time::timeout;
+
+    let link_info = entries.get_mut(link.as_str()).unwrap();
+    let item_type = gopher::item_type(link);
+    link_info.item_type = Some(item_type);
+    link_info.metatext = gopher::mime_for(item_type).to_string();
+
+    // search items need a query first
+    if item_type == '7' && link.query().is_none() {
+        link_info.input = Some(input::InputEndpoint {
+            kind: input::InputKind::Search,
+            prompt: String::new(),
+            sensitive: false,
+        });
+        return;
+    }
+
+    link_info.begin_fetch();
+    let result = timeout(fetcher.timeout, gopher::get(link, &fetcher.resolver)).await;
+    link_info.addresses = fetcher.resolver.addresses(link);
+    let response = match result {
+        Ok(Ok(r)) => r,
+        Ok(Err(e)) => {
+            warn!("failed to fetch {}: {}", link, e);
+            link_info.error = Some(e);
+            return;
+        },
+        Err(_) => {
+            link_info.timed_out = true;
+            return;
+        },
+    };
+
+    link_info.bytes = response.len();
+
+    let text = mime::decode(&response, "utf-8");
+    let links = match item_type {
+        '1' | '7' => gopher::menu_links(&text),
+        '0' => extract::bare_links(&text),
+        'h' => extract::html_links(&text),
+        _ => return,
+    };
+    handle_links(entries, frontier, link, links, true);
 }
 
 fn handle_gemtext(
     entries: &mut HashMap<String, UrlInfo>,
-    queue: &mut Vec<Url>,
+    index: &mut index::Index,
+    frontier: &mut frontier::Frontier,
     base_url: &Url,
-    data: Vec<u8>
+    text: &str,
+    follow_gopher: bool
+) {
+    let nodes = gemtext::parse(text);
+
+    // index the text...
+    index.add(base_url.as_str(), &nodes);
+    if let Some(info) = entries.get_mut(&base_url.to_string()) {
+        describe_page(info, &nodes);
+    }
+
+    // ...and follow the links
+    handle_links(entries, frontier, base_url, extract::gemtext_links(&nodes), follow_gopher);
+}
+
+fn handle_links(
+    entries: &mut HashMap<String, UrlInfo>,
+    frontier: &mut frontier::Frontier,
+    base_url: &Url,
+    links: Vec<extract::Link>,
+    follow_gopher: bool
 ) {
     // ...extract urls, and store them to crawl later
-    let urls = extract_urls(&base_url, data);
+    let (urls, external) = extract_urls(base_url, links, follow_gopher);
 
     for url in &urls {
-        status(queue.len(), entries.len(), urls.len());
+        if input::check(entries, url) == input::Submit::Sensitive {
+            continue;
+        }
 
-        if !entries.contains_key(&url.to_string()) {
-            queue.push(url.clone());
-            entries.insert(url.to_string(), UrlInfo::new(base_url.to_string()));
-        } else {
-            let info = entries.get_mut(&url.to_string()).unwrap();
-            info.referred_from.push(base_url.to_string());
+        match entries.get_mut(&url.to_string()) {
+            Some(info) => info.referred_from.push(base_url.to_string()),
+            None => {
+                frontier.push(url.clone());
+                entries.insert(url.to_string(), UrlInfo::new(base_url.to_string()));
+            },
         }
     }
+
+    // ...and keep note of where this page links to outside of geminispace;
+    // a page fetched again (the start page, on resume) links there afresh
+    if let Some(info) = entries.get_mut(&base_url.to_string()) {
+        info.external_links = external;
+    }
 }
 
-fn status(
-    queue_size: usize, entries: usize,
-    current_harvest: usize,
-) {
-    print!("\r{q:>0$} queued, {v:>0$} entries     ({ch} now)",
-        6, q = queue_size, v = entries, ch = current_harvest);
+fn describe_page(info: &mut UrlInfo, nodes: &[Node]) {
+    info.outline = nodes.iter()
+        .filter_map(|n| match n {
+            Node::Heading { level, body } => Some((*level, body.clone())),
+            _ => None,
+        })
+        .collect();
+
+    // the first heading, or else the first line of text
+    info.title = info.outline.first()
+        .map(|(_, h)| h.clone())
+        .or_else(|| nodes.iter().find_map(|n| match n {
+            Node::Text(t) if !t.trim().is_empty() => Some(t.trim().to_string()),
+            _ => None,
+        }));
+
+    info.links = nodes.iter()
+        .filter(|n| matches!(n, Node::Link { .. }))
+        .count();
+
+    info.words = nodes.iter()
+        .map(|n| match n {
+            Node::Text(t) | Node::ListItem(t) | Node::Quote(t)
+                | Node::Heading { body: t, .. } => t.split_whitespace().count(),
+            Node::Link { name: Some(name), .. } => name.split_whitespace().count(),
+            _ => 0,
+        })
+        .sum();
+}
+
+fn unix_time() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn load_data(path: &str) -> Result<HashMap<String, UrlInfo>, Box<dyn Error>> {
+    debug!("reading {}", path);
+    let json = fs::read_to_string(path)?;
+    let entries: HashMap<String, UrlInfo> = serde_json::from_str(&json)?;
+    info!("loaded {} entries from {}", entries.len(), path);
+
+    Ok(entries)
+}
+
+fn save_data(path: &str, entries: &HashMap<String, UrlInfo>) -> Result<(), Box<dyn Error>> {
+    write_file(path, serde_json::to_string(&entries)?.as_bytes())?;
+    info!("stored capsule data in {}", path);
+    Ok(())
 }
 
-fn save_data(entries: &mut HashMap<String, UrlInfo>) -> Result<(), Box<dyn Error>> {
-    fs::write(OUTFILE, serde_json::to_string(&entries)?.as_bytes())?;
-    println!("\nstored capsule data in {}", OUTFILE);
+/// Save everything we need to resume the crawl.
+fn checkpoint(files: &DataFiles, entries: &HashMap<String, UrlInfo>, index: &index::Index,
+    frontier: &frontier::Frontier) -> Result<(), Box<dyn Error>>
+{
+    save_data(&files.results, entries)?;
+    index.save(&files.index)?;
+    frontier.save(&files.frontier)?;
     Ok(())
 }
 
-fn extract_urls(base_url: &Url, data: Vec<u8>) -> Vec<Url> {
-    let data_s = data.iter().map(|b| *b as char)
-        .collect::<String>();
-    let parsed = gemtext::parse(&data_s);
+/// Write a file all at once: into a temporary file first, which then
+/// replaces the old one, so a crash never leaves half a file behind.
+fn write_file(path: &str, data: &[u8]) -> Result<(), Box<dyn Error>> {
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
 
+fn extract_urls(base_url: &Url, links: Vec<extract::Link>, follow_gopher: bool)
+    -> (Vec<Url>, Vec<ExternalLink>)
+{
     let mut found = Vec::new();
+    let mut external = Vec::new();
 
-    for node in parsed {
-        match node {
-            Node::Link { to, name: _ } => {
-                match parse_url(Some(&base_url), to.clone()) {
-                    Ok(u) => found.push(u),
-                    Err(_) => (),
-                }
-            },
-            _ => (),
+    for (to, name) in links {
+        let url = match resolve_url(Some(base_url), to) {
+            Ok(u) => u,
+            Err(_) => continue,
+        };
+
+        if url.scheme() == "gemini" {
+            if let Ok(u) = parse_url(None, url.as_str()) {
+                found.push(u);
+            }
+        } else if url.scheme() == "gopher" && follow_gopher {
+            found.push(url);
+        } else {
+            external.push(ExternalLink {
+                scheme: url.scheme().to_string(),
+                target: url.to_string(),
+                name,
+            });
         }
     }
 
-    found
+    (found, external)
 }
 
 fn parse_url<T>(base_u: Option<&Url>, u: T) -> Result<Url, Box<dyn Error>>
 where
     T: Into<String> + Clone
 {
-    // try to parse url
-    // if it fails because the url is relative, try again using
-    // base_u as the base url
-    let mut ur = match Url::parse(&u.clone().into()) {
-        Ok(u) => u,
-        Err(cause) => {
-            match cause {
-                ParseError::RelativeUrlWithoutBase => {
-                    if let Some(base) = base_u {
-                        base.join(&u.into())?
-                    } else {
-                        Err("gave relative url, but no base url")?
-                    }
-                },
-                _ => Err(cause)?,
-            }
-        },
-    };
+    let mut ur = resolve_url(base_u, u)?;
+
+    if ur.scheme() != "gemini" {
+        Err("invalid url scheme")?;
+    }
 
     if ur.port().is_none() {
         // meh, don't need to unwrap this
         let _ = ur.set_port(Some(1965));
     }
 
-    if ur.scheme() != "gemini" {
-        Err("invalid url scheme")?;
-    }
-
     Ok(ur)
 }
 
+fn resolve_url<T>(base_u: Option<&Url>, u: T) -> Result<Url, Box<dyn Error>>
+where
+    T: Into<String> + Clone
+{
+    // try to parse url
+    // if it fails because the url is relative, try again using
+    // base_u as the base url
+    match Url::parse(&u.clone().into()) {
+        Ok(u) => Ok(u),
+        Err(ParseError::RelativeUrlWithoutBase) => {
+            if let Some(base) = base_u {
+                Ok(base.join(&u.into())?)
+            } else {
+                Err("gave relative url, but no base url")?
+            }
+        },
+        Err(cause) => Err(cause)?,
+    }
+}
+
 // the following was stolen from Christine Dodrill's majc project
 // https://tulpa.dev/cadey/maj
 struct NoCertificateVerification {}
@@ -279,29 +828,126 @@ impl rustls::ServerCertVerifier for NoCertificateVerification {
     }
 }
 
-async fn get(ur: &Url, cfg: tokio_rustls::rustls::ClientConfig)
-    -> Result<Vec<u8>, Box<dyn std::error::Error>>
+async fn get(ur: &Url, cfg: tokio_rustls::rustls::ClientConfig, resolver: &net::Resolver)
+    -> Result<Vec<u8>, FetchError>
 {
     use tokio::io::{AsyncWriteExt, AsyncReadExt};
 
-    let cfg = Arc::new(cfg);
-    let host = match ur.host_str() {
-        Some(h) => h,
-        None => return Err("url's host str == None")?,
-    };
+    let mut cfg = cfg;
+    let host = net::Host::of(ur)?;
 
-    let name_ref = webpki::DNSNameRef::try_from_ascii_str(host)?;
-    let config = TlsConnector::from(cfg);
+    // IP literals aren't valid in SNI; the name is then only used for
+    // verifying the certificate, which we don't do anyway
+    let name = match &host {
+        net::Host::Name(name) => name.as_str(),
+        net::Host::Ip(_) => {
+            cfg.enable_sni = false;
+            "invalid"
+        },
+    };
+    let name_ref = webpki::DNSNameRef::try_from_ascii_str(name)
+        .map_err(|_| FetchError::new(ErrorClass::InvalidSniName,
+            format!("invalid SNI name: {}", name)))?;
+    let config = TlsConnector::from(Arc::new(cfg));
 
-    let sock = TcpStream::connect(&format!("{}:{}", host,
-            ur.port().unwrap())).await?;
-    let mut tls = config.connect(name_ref, sock).await?;
+    let sock = resolver.connect(&host, ur.port().unwrap()).await?;
+    let mut tls = config.connect(name_ref, sock).await.map_err(|e| match e.kind() {
+        std::io::ErrorKind::ConnectionReset => FetchError::io(e, "TLS handshake"),
+        _ => FetchError::new(ErrorClass::TlsHandshake, format!("TLS handshake: {}", e)),
+    })?;
 
-    let req = format!("{}\r\n", ur.to_string());
+    let req = format!("{}\r\n", net::request_url(ur, &host));
 
-    tls.write(req.as_bytes()).await?;
+    tls.write_all(req.as_bytes()).await
+        .map_err(|e| FetchError::io(e, "sending request"))?;
     let mut buf: Vec<u8> = vec![];
-    tls.read_to_end(&mut buf).await?;
+    if let Err(e) = tls.read_to_end(&mut buf).await {
+        return Err(FetchError::reading(e, &buf));
+    }
 
     Ok(buf)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn describe(text: &str) -> UrlInfo {
+        let mut info = UrlInfo::default();
+        describe_page(&mut info, &gemtext::parse(text));
+        info
+    }
+
+    #[test]
+    fn page_with_headings() {
+        let info = describe("intro line\n# Title\nsome text here\n## Part\n=> /a A link\n=> /b\n");
+        assert_eq!(info.title.as_deref(), Some("Title"));
+        assert_eq!(info.outline, vec![(1, "Title".to_string()), (2, "Part".to_string())]);
+        assert_eq!(info.links, 2);
+        assert_eq!(info.words, 2 + 1 + 3 + 1 + 2);
+    }
+
+    #[test]
+    fn page_without_headings() {
+        let info = describe("\n   \n  First line  \n* item one\n> quoted\n");
+        assert_eq!(info.title.as_deref(), Some("First line"));
+        assert!(info.outline.is_empty());
+        assert_eq!(info.words, 2 + 2 + 1);
+    }
+
+    #[test]
+    fn preformatted_page() {
+        let info = describe("```\n# not a heading\nascii art\n```\n");
+        assert_eq!(info.title, None);
+        assert!(info.outline.is_empty());
+        assert_eq!((info.links, info.words), (0, 0));
+    }
+
+    #[test]
+    fn links_again() {
+        let base = Url::parse("gemini://example.org:1965/").unwrap();
+        let mut entries = HashMap::new();
+        entries.insert(base.to_string(), UrlInfo::default());
+        let mut frontier = frontier::Frontier::default();
+        let links = vec![
+            ("https://example.com/".to_string(), Some("web".to_string())),
+            ("/page".to_string(), None),
+        ];
+
+        // the start page is fetched again on resume
+        handle_links(&mut entries, &mut frontier, &base, links.clone(), false);
+        handle_links(&mut entries, &mut frontier, &base, links, false);
+
+        let external = &entries[base.as_str()].external_links;
+        assert_eq!(external.len(), 1);
+        assert_eq!(external[0].target, "https://example.com/");
+        assert_eq!(frontier.len(), 1);
+    }
+
+    #[test]
+    fn data_files() {
+        let files = DataFiles::next_to("crawls/backup.json");
+        assert_eq!(files.results, "crawls/backup.json");
+        assert_eq!(files.index, "crawls/index.json");
+        assert_eq!(files.frontier, "crawls/frontier.json");
+        assert_eq!(DataFiles::next_to(OUTFILE).frontier, FRONTIERFILE);
+    }
+
+    #[test]
+    fn write_whole_file() {
+        let path = std::env::temp_dir().join(format!("gc-write-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "old").unwrap();
+        let mut old = fs::File::open(path).unwrap();
+
+        write_file(path, b"new").unwrap();
+
+        // the old file was replaced, not written over
+        let mut s = String::new();
+        std::io::Read::read_to_string(&mut old, &mut s).unwrap();
+        assert_eq!(s, "old");
+        assert_eq!(fs::read_to_string(path).unwrap(), "new");
+        assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+        fs::remove_file(path).unwrap();
+    }
+}
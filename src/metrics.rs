@@ -0,0 +1,194 @@
+use log::{info, warn};
+use tokio::net::{TcpListener, TcpStream};
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::error::ErrorClass;
+use crate::UrlInfo;
+
+// upper bounds of the latency histogram buckets, in seconds
+const BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+const REQUEST_TIMEOUT_MS: u64 = 5000;
+
+/// Counters and gauges about the crawl, for Prometheus to scrape.
+#[derive(Default)]
+pub struct Metrics {
+    /// "2x" etc., "gopher" for gopher responses, or "none" when there was
+    /// no response
+    fetches: BTreeMap<String, u64>,
+    errors: BTreeMap<ErrorClass, u64>,
+    timeouts: u64,
+    bytes: u64,
+    queued: usize,
+    entries: usize,
+    // counts per bucket (not cumulative), and one for +Inf
+    latency: Vec<u64>,
+    latency_sum: f64,
+}
+
+impl Metrics {
+    /// Count a fetch of a page and how long it took.
+    pub fn fetched(&mut self, info: &UrlInfo, took: Duration) {
+        let status = match info.response_code {
+            0 if info.answered() => "gopher".to_string(),
+            0 => "none".to_string(),
+            code => format!("{}x", code / 10),
+        };
+        *self.fetches.entry(status).or_insert(0) += 1;
+
+        if let Some(e) = &info.error {
+            *self.errors.entry(e.class).or_insert(0) += 1;
+        }
+        if info.timed_out {
+            self.timeouts += 1;
+        }
+        self.bytes += info.bytes as u64;
+
+        let secs = took.as_secs_f64();
+        if self.latency.is_empty() {
+            self.latency = vec![0; BUCKETS.len() + 1];
+        }
+        let bucket = BUCKETS.iter().position(|b| secs <= *b).unwrap_or(BUCKETS.len());
+        self.latency[bucket] += 1;
+        self.latency_sum += secs;
+    }
+
+    pub fn set_sizes(&mut self, queued: usize, entries: usize) {
+        self.queued = queued;
+        self.entries = entries;
+    }
+
+    /// Everything in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        metric(&mut out, "gc_fetches_total", "counter", "Pages fetched, by status category.");
+        for (status, n) in &self.fetches {
+            let _ = writeln!(out, "gc_fetches_total{{status=\"{}\"}} {}", status, n);
+        }
+
+        metric(&mut out, "gc_fetch_errors_total", "counter", "Failed fetches, by error class.");
+        for (class, n) in &self.errors {
+            let class = serde_json::to_string(class).unwrap_or_default();
+            let _ = writeln!(out, "gc_fetch_errors_total{{class={}}} {}", class, n);
+        }
+
+        metric(&mut out, "gc_timeouts_total", "counter", "Fetches that timed out.");
+        let _ = writeln!(out, "gc_timeouts_total {}", self.timeouts);
+
+        metric(&mut out, "gc_bytes_total", "counter", "Bytes of responses received.");
+        let _ = writeln!(out, "gc_bytes_total {}", self.bytes);
+
+        metric(&mut out, "gc_queue_size", "gauge", "Urls waiting to be fetched.");
+        let _ = writeln!(out, "gc_queue_size {}", self.queued);
+
+        metric(&mut out, "gc_entries", "gauge", "Urls known.");
+        let _ = writeln!(out, "gc_entries {}", self.entries);
+
+        metric(&mut out, "gc_fetch_duration_seconds", "histogram", "How long fetches took.");
+        let mut count = 0;
+        for (i, bound) in BUCKETS.iter().enumerate() {
+            count += self.latency.get(i).copied().unwrap_or(0);
+            let _ = writeln!(out, "gc_fetch_duration_seconds_bucket{{le=\"{}\"}} {}", bound, count);
+        }
+        count += self.latency.get(BUCKETS.len()).copied().unwrap_or(0);
+        let _ = writeln!(out, "gc_fetch_duration_seconds_bucket{{le=\"+Inf\"}} {}", count);
+        let _ = writeln!(out, "gc_fetch_duration_seconds_sum {}", self.latency_sum);
+        let _ = writeln!(out, "gc_fetch_duration_seconds_count {}", count);
+
+        out
+    }
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Answer every HTTP request on the listener with the metrics.
+pub async fn serve(mut listener: TcpListener, metrics: Arc<Mutex<Metrics>>) {
+    if let Ok(addr) = listener.local_addr() {
+        info!("serving metrics on http://{}/metrics", addr);
+    }
+
+    loop {
+        let (sock, peer) = match listener.accept().await {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("metrics: {}", e);
+                continue;
+            },
+        };
+
+        let metrics = metrics.clone();
+        smol::Task::spawn(async move {
+            if let Err(e) = handle(sock, &metrics).await {
+                warn!("metrics: {}: {}", peer, e);
+            }
+        }).detach();
+    }
+}
+
+async fn handle(mut sock: TcpStream, metrics: &Mutex<Metrics>) -> Result<(), Box<dyn Error>> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::time::timeout;
+
+    // we don't care what was asked for, but let the client finish asking
+    let mut buf = [0u8; 1024];
+    timeout(Duration::from_millis(REQUEST_TIMEOUT_MS), sock.read(&mut buf)).await??;
+
+    let body = metrics.lock().unwrap().render();
+    let response = format!("HTTP/1.1 200 OK\r\n\
+        Content-Type: text/plain; version=0.0.4\r\n\
+        Content-Length: {}\r\n\
+        Connection: close\r\n\r\n{}", body.len(), body);
+    sock.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+/// Start serving metrics on `listen`, in the background.
+pub async fn spawn(listen: &str, metrics: Arc<Mutex<Metrics>>) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(listen).await?;
+    smol::Task::spawn(serve(listener, metrics)).detach();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::FetchError;
+
+    #[test]
+    fn render() {
+        let mut metrics = Metrics::default();
+        let ok = UrlInfo { response_code: 20, bytes: 100, ..Default::default() };
+        metrics.fetched(&ok, Duration::from_millis(80));
+        metrics.fetched(&ok, Duration::from_secs(20));
+        let dns = UrlInfo {
+            error: Some(FetchError::new(ErrorClass::DnsFailure, "x")),
+            ..Default::default()
+        };
+        metrics.fetched(&dns, Duration::from_millis(10));
+        metrics.set_sizes(5, 9);
+
+        let text = metrics.render();
+        for line in &[
+            "gc_fetches_total{status=\"2x\"} 2",
+            "gc_fetches_total{status=\"none\"} 1",
+            "gc_fetch_errors_total{class=\"dns_failure\"} 1",
+            "gc_bytes_total 200",
+            "gc_queue_size 5",
+            "gc_fetch_duration_seconds_bucket{le=\"0.05\"} 1",
+            "gc_fetch_duration_seconds_bucket{le=\"0.1\"} 2",
+            "gc_fetch_duration_seconds_bucket{le=\"10\"} 2",
+            "gc_fetch_duration_seconds_bucket{le=\"+Inf\"} 3",
+            "gc_fetch_duration_seconds_count 3",
+        ] {
+            assert!(text.lines().any(|l| l == *line), "missing {}", line);
+        }
+    }
+}
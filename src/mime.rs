@@ -0,0 +1,104 @@
+use encoding_rs::Encoding;
+
+/// The MIME type from the meta of a 2x response, split into the type itself
+/// and its parameters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mime {
+    /// type/subtype, lowercased
+    pub essence: String,
+    /// (lowercased key, value)
+    pub params: Vec<(String, String)>,
+}
+
+impl Mime {
+    /// Parse the meta of a 2x response. An empty meta is the same as
+    /// `text/gemini; charset=utf-8`, as the specification says.
+    pub fn parse(meta: &str) -> Self {
+        let meta = meta.trim();
+        if meta.is_empty() {
+            return Self {
+                essence: "text/gemini".to_string(),
+                params: vec![("charset".to_string(), "utf-8".to_string())],
+            };
+        }
+
+        let mut parts = meta.split(';');
+        let essence = parts.next().unwrap_or("").trim().to_lowercase();
+        let params = parts
+            .filter_map(|p| {
+                let mut kv = p.splitn(2, '=');
+                let k = kv.next()?.trim().to_lowercase();
+                let v = kv.next()?.trim().trim_matches('"').to_string();
+                Some((k, v))
+            })
+            .collect();
+
+        Self { essence, params }
+    }
+
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.params.iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.essence.starts_with("text/")
+    }
+
+    /// The declared charset, or utf-8 if there is none.
+    pub fn charset(&self) -> &str {
+        self.param("charset").unwrap_or("utf-8")
+    }
+
+    /// The declared language(s), e.g. `en` or `en,fr`.
+    pub fn lang(&self) -> Option<&str> {
+        self.param("lang")
+    }
+
+    /// Whether the type is the wanted one; a wanted type without a subtype
+    /// (`image`) or with a `*` subtype (`image/*`) matches any subtype.
+    pub fn matches(&self, wanted: &str) -> bool {
+        let wanted = wanted.trim_end_matches("/*");
+        self.essence == wanted
+            || (!wanted.contains('/') && self.essence.starts_with(&format!("{}/", wanted)))
+    }
+}
+
+/// Decode a text body according to its charset. Charsets we don't know are
+/// treated as utf-8; invalid sequences are replaced either way.
+pub fn decode(body: &[u8], charset: &str) -> String {
+    let encoding = Encoding::for_label(charset.trim().as_bytes())
+        .unwrap_or(encoding_rs::UTF_8);
+    let (text, _, _) = encoding.decode(body);
+    text.into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let mime = Mime::parse("Text/Gemini; charset=ISO-8859-1;lang=\"en-GB\"");
+        assert_eq!(mime.essence, "text/gemini");
+        assert_eq!(mime.charset(), "ISO-8859-1");
+        assert_eq!(mime.lang(), Some("en-GB"));
+
+        let empty = Mime::parse("");
+        assert_eq!(empty.essence, "text/gemini");
+        assert_eq!(empty.charset(), "utf-8");
+
+        let png = Mime::parse("image/png");
+        assert!(png.matches("image") && png.matches("image/*") && png.matches("image/png"));
+        assert!(!png.matches("text") && !png.matches("image/jpeg"));
+    }
+
+    #[test]
+    fn charsets() {
+        assert_eq!(decode(b"caf\xe9", "iso-8859-1"), "café");
+        assert_eq!(decode("café".as_bytes(), "utf-8"), "café");
+        assert_eq!(decode("café".as_bytes(), "no-such-charset"), "café");
+        assert_eq!(decode(b"caf\xe9", "utf-8"), "caf\u{fffd}");
+    }
+}
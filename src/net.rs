@@ -0,0 +1,310 @@
+use percent_encoding::percent_decode_str;
+use tokio::net::TcpStream;
+use url::Url;
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::{NetConfig, ProxyConfig};
+use crate::error::{ErrorClass, FetchError};
+
+/// Where to connect to for a url: a name to resolve, or an address as it
+/// was written in the url.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Host {
+    /// an ASCII (punycode) domain name
+    Name(String),
+    Ip(IpAddr),
+}
+
+impl Host {
+    /// The host of a url. Gemini urls aren't "special" to the url crate, so
+    /// it neither parses IPv4 addresses nor converts IDNs for them.
+    pub fn of(url: &Url) -> Result<Self, FetchError> {
+        let invalid = |name: &str| FetchError::new(ErrorClass::InvalidHost,
+            format!("invalid host name: {}", name));
+
+        match url.host() {
+            Some(url::Host::Ipv4(ip)) => Ok(Host::Ip(ip.into())),
+            Some(url::Host::Ipv6(ip)) => Ok(Host::Ip(ip.into())),
+            Some(url::Host::Domain(d)) => {
+                let name = percent_decode_str(d).decode_utf8().map_err(|_| invalid(d))?;
+                if let Ok(ip) = name.parse::<IpAddr>() {
+                    return Ok(Host::Ip(ip));
+                }
+
+                match idna::domain_to_ascii(&name) {
+                    Ok(ascii) if !ascii.is_empty() => Ok(Host::Name(ascii)),
+                    _ => Err(invalid(&name)),
+                }
+            },
+            None => Err(FetchError::new(ErrorClass::InvalidHost, "url's host str == None")),
+        }
+    }
+}
+
+/// The url as it should be sent in a request, with an IDN host in punycode.
+pub fn request_url(url: &Url, host: &Host) -> Url {
+    let mut url = url.clone();
+    if let Host::Name(name) = host {
+        if url.host_str() != Some(name.as_str()) {
+            let _ = url.set_host(Some(name));
+        }
+    }
+    url
+}
+
+/// Resolves host names, caching the answers for a while, and spaces out
+/// connections to the same address, however many names it has.
+pub struct Resolver {
+    ttl: Duration,
+    delay: Duration,
+    overrides: HashMap<String, IpAddr>,
+    proxies: Vec<ProxyConfig>,
+    // name -> addresses, and when we looked them up
+    cache: Mutex<HashMap<String, (Vec<IpAddr>, Instant)>>,
+    // address -> when we last connected to it
+    last_connect: Mutex<HashMap<IpAddr, Instant>>,
+}
+
+impl Resolver {
+    pub fn new(cfg: &NetConfig) -> Self {
+        Self {
+            ttl: Duration::from_secs(cfg.dns_ttl_secs),
+            delay: Duration::from_millis(cfg.per_ip_delay_ms),
+            overrides: cfg.hosts.iter()
+                .map(|(name, ip)| (ascii_name(name), *ip))
+                .collect(),
+            proxies: cfg.proxies.iter()
+                .map(|p| ProxyConfig { hosts: ascii_pattern(&p.hosts), socks5: p.socks5.clone() })
+                .collect(),
+            cache: Mutex::new(HashMap::new()),
+            last_connect: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn resolve(&self, host: &Host, port: u16) -> Result<Vec<IpAddr>, FetchError> {
+        let failed = |e: String| FetchError::new(ErrorClass::DnsFailure,
+            format!("resolving {}: {}", host, e));
+
+        let name = match host {
+            Host::Ip(ip) => return Ok(vec![*ip]),
+            Host::Name(name) => name,
+        };
+
+        if let Some(ip) = self.overrides.get(name) {
+            return Ok(vec![*ip]);
+        }
+
+        if let Some((addrs, at)) = self.cache.lock().unwrap().get(name) {
+            if at.elapsed() < self.ttl {
+                return Ok(addrs.clone());
+            }
+        }
+
+        let mut addrs = Vec::new();
+        for addr in tokio::net::lookup_host((name.as_str(), port)).await
+            .map_err(|e| failed(e.to_string()))?
+        {
+            if !addrs.contains(&addr.ip()) {
+                addrs.push(addr.ip());
+            }
+        }
+        if addrs.is_empty() {
+            return Err(failed("no addresses".to_string()));
+        }
+
+        self.cache.lock().unwrap().insert(name.clone(), (addrs.clone(), Instant::now()));
+        Ok(addrs)
+    }
+
+    /// The addresses we last resolved for the url's host, if any.
+    pub fn addresses(&self, url: &Url) -> Vec<IpAddr> {
+        match Host::of(url) {
+            Ok(Host::Ip(ip)) => vec![ip],
+            Ok(Host::Name(name)) => match self.overrides.get(&name) {
+                Some(ip) => vec![*ip],
+                None => self.cache.lock().unwrap().get(&name)
+                    .map(|(addrs, _)| addrs.clone())
+                    .unwrap_or_default(),
+            },
+            Err(_) => vec![],
+        }
+    }
+
+    /// Connect to the first address of the host that accepts, or through
+    /// its proxy if it has one.
+    pub async fn connect(&self, host: &Host, port: u16) -> Result<TcpStream, FetchError> {
+        if let Some(proxy) = self.proxy_for(host) {
+            // the proxy resolves names, unless we've pinned them
+            let target = match host {
+                Host::Name(name) => self.overrides.get(name)
+                    .map(|ip| Host::Ip(*ip))
+                    .unwrap_or_else(|| host.clone()),
+                Host::Ip(_) => host.clone(),
+            };
+            return crate::socks::connect(&proxy.socks5, &target, port).await;
+        }
+
+        let mut last_err = None;
+
+        for ip in self.resolve(host, port).await? {
+            self.wait_for(ip).await;
+
+            match TcpStream::connect(SocketAddr::new(ip, port)).await {
+                Ok(sock) => return Ok(sock),
+                Err(e) => last_err = Some(e),
+            }
+        }
+
+        let e = last_err.unwrap_or_else(|| std::io::ErrorKind::NotFound.into());
+        Err(FetchError::io(e, &connect_string(host, port)))
+    }
+
+    fn proxy_for(&self, host: &Host) -> Option<&ProxyConfig> {
+        let name = host.to_string();
+        self.proxies.iter().find(|p| host_matches(&p.hosts, &name))
+    }
+
+    async fn wait_for(&self, ip: IpAddr) {
+        let wait = self.last_connect.lock().unwrap().get(&ip)
+            .map(|last| self.delay.checked_sub(last.elapsed()).unwrap_or_default());
+        if let Some(wait) = wait {
+            tokio::time::delay_for(wait).await;
+        }
+        self.last_connect.lock().unwrap().insert(ip, Instant::now());
+    }
+}
+
+impl std::fmt::Display for Host {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Host::Name(name) => f.write_str(name),
+            Host::Ip(ip) => write!(f, "{}", ip),
+        }
+    }
+}
+
+/// A host name as we compare it with those of urls: lowercase, and with
+/// IDNs in punycode.
+pub fn ascii_name(name: &str) -> String {
+    idna::domain_to_ascii(name).unwrap_or_else(|_| name.to_lowercase())
+}
+
+fn ascii_pattern(pattern: &str) -> String {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => format!("*.{}", ascii_name(domain)),
+        None if pattern == "*" => pattern.to_string(),
+        None => ascii_name(pattern),
+    }
+}
+
+/// Whether a host matches a pattern: the host itself, `*.domain` for the
+/// domain and everything below it, or `*`.
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*") {
+        Some("") => true,
+        Some(suffix) if suffix.starts_with('.') => {
+            host.ends_with(suffix) || host == &suffix[1..]
+        },
+        _ => pattern == host,
+    }
+}
+
+/// host:port, with IPv6 addresses in brackets.
+pub fn connect_string(host: &Host, port: u16) -> String {
+    match host {
+        Host::Ip(IpAddr::V6(ip)) => format!("[{}]:{}", ip, port),
+        Host::Ip(ip) => format!("{}:{}", ip, port),
+        Host::Name(name) => format!("{}:{}", name, port),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(u: &str) -> Host {
+        Host::of(&Url::parse(u).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn hosts() {
+        assert_eq!(host("gemini://127.0.0.1:1965/"), Host::Ip("127.0.0.1".parse().unwrap()));
+        assert_eq!(host("gemini://[::1]:1965/"), Host::Ip("::1".parse().unwrap()));
+        assert_eq!(host("gemini://Bücher.example/"), Host::Name("xn--bcher-kva.example".to_string()));
+        assert_eq!(host("gemini://a.example/"), Host::Name("a.example".to_string()));
+
+        assert_eq!(connect_string(&host("gemini://[::1]/"), 1965), "[::1]:1965");
+        assert_eq!(connect_string(&host("gemini://10.0.0.1/"), 1965), "10.0.0.1:1965");
+
+        let url = Url::parse("gemini://bücher.example:1965/x").unwrap();
+        assert_eq!(request_url(&url, &host(url.as_str())).as_str(),
+            "gemini://xn--bcher-kva.example:1965/x");
+    }
+
+    #[test]
+    fn cache() {
+        let resolver = Resolver::new(&NetConfig { dns_ttl_secs: 60, ..Default::default() });
+        let cached: IpAddr = "192.0.2.1".parse().unwrap();
+        let name = Host::Name("localhost".to_string());
+
+        // what's cached is used as long as it's fresh...
+        resolver.cache.lock().unwrap().insert("localhost".to_string(), (vec![cached], Instant::now()));
+        assert_eq!(smol::run(resolver.resolve(&name, 1965)).unwrap(), vec![cached]);
+        assert_eq!(resolver.addresses(&Url::parse("gemini://localhost/").unwrap()), vec![cached]);
+
+        // ...and looked up again once it's not
+        let old = Instant::now().checked_sub(Duration::from_secs(61)).unwrap();
+        resolver.cache.lock().unwrap().insert("localhost".to_string(), (vec![cached], old));
+        let addrs = smol::run(resolver.resolve(&name, 1965)).unwrap();
+        assert!(!addrs.contains(&cached) && addrs.iter().all(|a| a.is_loopback()), "{:?}", addrs);
+        assert_eq!(resolver.addresses(&Url::parse("gemini://localhost/").unwrap()), addrs);
+    }
+
+    #[test]
+    fn per_ip_delay() {
+        let resolver = Resolver::new(&NetConfig { per_ip_delay_ms: 100, ..Default::default() });
+        let (a, b): (IpAddr, IpAddr) = ("192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap());
+
+        let took = |ip| smol::run(async {
+            let start = Instant::now();
+            resolver.wait_for(ip).await;
+            start.elapsed()
+        });
+        assert!(took(a) < Duration::from_millis(50));
+        assert!(took(a) >= Duration::from_millis(80));
+        assert!(took(b) < Duration::from_millis(50));
+    }
+
+    #[test]
+    fn overrides() {
+        let pinned: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut cfg = NetConfig::default();
+        cfg.hosts.insert("Bücher.example".to_string(), pinned);
+        cfg.proxies.push(ProxyConfig { hosts: "*.Bücher.example".to_string(), socks5: "p:1080".to_string() });
+        let resolver = Resolver::new(&cfg);
+
+        let url = Url::parse("gemini://bücher.example/").unwrap();
+        assert_eq!(smol::run(resolver.resolve(&host(url.as_str()), 1965)).unwrap(), vec![pinned]);
+        assert_eq!(resolver.addresses(&url), vec![pinned]);
+        assert_eq!(resolver.addresses(&Url::parse("gemini://xn--bcher-kva.example/").unwrap()), vec![pinned]);
+        assert!(resolver.proxy_for(&host("gemini://www.bücher.example/")).is_some());
+        assert!(resolver.proxy_for(&host("gemini://other.example/")).is_none());
+
+        assert_eq!(ascii_pattern("*"), "*");
+        assert_eq!(ascii_pattern("A.Example"), "a.example");
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(host_matches("*.onion", "abc.onion"));
+        assert!(host_matches("*.onion", "onion"));
+        assert!(!host_matches("*.onion", "notonion"));
+        assert!(host_matches("*", "a.example"));
+        assert!(host_matches("a.example", "a.example"));
+        assert!(!host_matches("a.example", "b.a.example"));
+    }
+}
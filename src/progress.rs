@@ -0,0 +1,276 @@
+use url::Url;
+
+use std::collections::VecDeque;
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::frontier::Frontier;
+
+// how many lines of the dashboard are on screen
+static DRAWN: AtomicUsize = AtomicUsize::new(0);
+
+const REDRAW: Duration = Duration::from_millis(250);
+// without a terminal, print a plain line this often
+const PLAIN_EVERY: Duration = Duration::from_secs(10);
+// rates are over this much of the recent past
+const WINDOW: Duration = Duration::from_secs(30);
+const TOP_HOSTS: usize = 5;
+
+/// How the crawl is going, shown as a dashboard on a terminal or as a line
+/// now and then otherwise.
+pub struct Progress {
+    tty: bool,
+    start: Instant,
+    fetched: usize,
+    errors: usize,
+    bytes: usize,
+    // the url being fetched, and since when
+    current: Option<(String, Instant)>,
+    queued: usize,
+    entries: usize,
+    top_hosts: Vec<(String, usize)>,
+    hosts_counted: Option<Instant>,
+    // (when, fetched, errors, bytes) for the rates
+    samples: VecDeque<(Instant, usize, usize, usize)>,
+}
+
+/// What goes into a single redraw.
+struct Snapshot {
+    elapsed: Duration,
+    fetched: usize,
+    errors: usize,
+    current: Option<(String, Duration)>,
+    queued: usize,
+    entries: usize,
+    // per second, over the window
+    rate: f64,
+    error_rate: f64,
+    bytes_rate: f64,
+    top_hosts: Vec<(String, usize)>,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self {
+            tty: std::io::stdout().is_terminal(),
+            start: Instant::now(),
+            fetched: 0,
+            errors: 0,
+            bytes: 0,
+            current: None,
+            queued: 0,
+            entries: 0,
+            top_hosts: Vec::new(),
+            hosts_counted: None,
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn started(&mut self, url: &Url) {
+        self.current = Some((url.to_string(), Instant::now()));
+    }
+
+    /// We didn't fetch the url after all.
+    pub fn cancelled(&mut self) {
+        self.current = None;
+    }
+
+    pub fn finished(&mut self, bytes: usize, failed: bool) {
+        self.current = None;
+        self.fetched += 1;
+        self.bytes += bytes;
+        if failed {
+            self.errors += 1;
+        }
+    }
+
+    /// Keep up with what's left. The most queued hosts take a while to
+    /// count, so that's only done as often as they're shown.
+    pub fn set_sizes(&mut self, frontier: &Frontier, entries: usize) {
+        self.queued = frontier.len();
+        self.entries = entries;
+
+        if self.tty && self.hosts_counted.map(|at| at.elapsed() >= REDRAW).unwrap_or(true) {
+            self.top_hosts = frontier.top_hosts(TOP_HOSTS);
+            self.hosts_counted = Some(Instant::now());
+        }
+    }
+
+    fn snapshot(&mut self, now: Instant) -> Snapshot {
+        self.samples.push_back((now, self.fetched, self.errors, self.bytes));
+        while self.samples.len() > 1 && now - self.samples[0].0 > WINDOW {
+            self.samples.pop_front();
+        }
+        let (then, fetched, errors, bytes) = self.samples[0];
+        let secs = (now - then).as_secs_f64().max(1.0);
+
+        Snapshot {
+            elapsed: now - self.start,
+            fetched: self.fetched,
+            errors: self.errors,
+            current: self.current.as_ref().map(|(url, since)| (url.clone(), now - *since)),
+            queued: self.queued,
+            entries: self.entries,
+            rate: (self.fetched - fetched) as f64 / secs,
+            error_rate: (self.errors - errors) as f64 / secs,
+            bytes_rate: (self.bytes - bytes) as f64 / secs,
+            top_hosts: self.top_hosts.clone(),
+        }
+    }
+
+    fn draw(&mut self) {
+        let snapshot = self.snapshot(Instant::now());
+        if self.tty {
+            draw(&snapshot.dashboard());
+        } else {
+            println!("{}", snapshot.line());
+        }
+    }
+}
+
+/// Redraw every so often, in the background, so that a fetch that takes
+/// long doesn't hold the progress up too. Cancel the task to stop.
+pub fn spawn(progress: Arc<Mutex<Progress>>) -> smol::Task<()> {
+    let every = if progress.lock().unwrap().tty { REDRAW } else { PLAIN_EVERY };
+
+    smol::Task::spawn(async move {
+        loop {
+            tokio::time::delay_for(every).await;
+            progress.lock().unwrap().draw();
+        }
+    })
+}
+
+impl Snapshot {
+    fn eta(&self) -> String {
+        if self.rate > 0.0 {
+            duration(Duration::from_secs_f64(self.queued as f64 / self.rate))
+        } else {
+            "-".to_string()
+        }
+    }
+
+    fn dashboard(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("elapsed {}   eta {} (for what's queued now)", duration(self.elapsed), self.eta()),
+            format!("fetched {:>8}   {:>7.2}/s   {:>10}/s",
+                self.fetched, self.rate, bytes(self.bytes_rate)),
+            format!("errors  {:>8}   {:>7.2}/s   ({:.1}% of all)",
+                self.errors, self.error_rate, percent(self.errors, self.fetched)),
+            format!("queued  {:>8}   entries {}", self.queued, self.entries),
+        ];
+
+        if let Some((url, took)) = &self.current {
+            lines.push(format!("fetching {} ({})", url, duration(*took)));
+        }
+
+        if !self.top_hosts.is_empty() {
+            lines.push("most queued:".to_string());
+            for (host, n) in &self.top_hosts {
+                lines.push(format!("{:>8}  {}", n, host));
+            }
+        }
+        lines
+    }
+
+    fn line(&self) -> String {
+        format!("{} elapsed: {} fetched ({:.2}/s, {}/s), {} errors ({:.1}%), {} queued, {} entries, eta {}",
+            duration(self.elapsed), self.fetched, self.rate, bytes(self.bytes_rate),
+            self.errors, percent(self.errors, self.fetched), self.queued, self.entries, self.eta())
+    }
+}
+
+fn draw(lines: &[String]) {
+    // hold stdout, so that nothing gets between clearing and drawing
+    let mut out = std::io::stdout().lock();
+    clear();
+
+    let _ = write!(out, "{}", lines.join("\n"));
+    let _ = out.flush();
+    DRAWN.store(lines.len(), Ordering::Relaxed);
+}
+
+/// Take the dashboard off the screen, so that something else can be
+/// printed; the next redraw puts it back.
+pub fn clear() {
+    let mut out = std::io::stdout().lock();
+    let drawn = DRAWN.swap(0, Ordering::Relaxed);
+    if drawn == 0 {
+        return;
+    }
+
+    // we're at the end of the last line
+    if drawn > 1 {
+        let _ = write!(out, "\x1b[{}A", drawn - 1);
+    }
+    let _ = write!(out, "\r\x1b[J");
+    let _ = out.flush();
+}
+
+fn percent(n: usize, of: usize) -> f64 {
+    if of == 0 { 0.0 } else { n as f64 * 100.0 / of as f64 }
+}
+
+fn duration(d: Duration) -> String {
+    let s = d.as_secs();
+    format!("{:02}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+}
+
+fn bytes(n: f64) -> String {
+    match n {
+        n if n >= 1024.0 * 1024.0 => format!("{:.1} MiB", n / 1024.0 / 1024.0),
+        n if n >= 1024.0 => format!("{:.1} KiB", n / 1024.0),
+        n => format!("{:.0} B", n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates() {
+        let mut progress = Progress::new();
+        let start = progress.start;
+
+        progress.snapshot(start);
+        for _ in 0..30 {
+            progress.finished(1024, false);
+        }
+        progress.finished(0, true);
+        progress.finished(0, true);
+        progress.queued = 64;
+
+        let s = progress.snapshot(start + Duration::from_secs(8));
+        assert_eq!((s.fetched, s.errors), (32, 2));
+        assert_eq!((s.rate, s.error_rate, s.bytes_rate), (4.0, 0.25, 3840.0));
+        assert_eq!(s.eta(), "00:00:16");
+
+        // only the last WINDOW counts
+        let s = progress.snapshot(start + Duration::from_secs(60));
+        assert_eq!((s.rate, s.eta()), (0.0, "-".to_string()));
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(duration(Duration::from_secs(3 * 3600 + 25 * 60 + 7)), "03:25:07");
+        assert_eq!(bytes(512.0), "512 B");
+        assert_eq!(bytes(1536.0), "1.5 KiB");
+        assert_eq!(bytes(3.0 * 1024.0 * 1024.0), "3.0 MiB");
+        assert_eq!(percent(1, 4), 25.0);
+        assert_eq!(percent(1, 0), 0.0);
+
+        let mut progress = Progress::new();
+        let start = progress.start;
+        progress.snapshot(start);
+        progress.finished(2048, false);
+        progress.finished(0, true);
+        progress.queued = 7;
+        progress.entries = 12;
+        let line = progress.snapshot(start + Duration::from_secs(2)).line();
+        assert_eq!(line, "00:00:02 elapsed: 2 fetched (1.00/s, 1.0 KiB/s), 1 errors (50.0%), \
+            7 queued, 12 entries, eta 00:00:07");
+    }
+}
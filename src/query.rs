@@ -0,0 +1,338 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use crate::graph::host_of;
+use crate::index::{tokenize, Index};
+use crate::mime::Mime;
+use crate::UrlInfo;
+
+/// A search query, e.g.
+///
+/// ```text
+/// rust "tiny web" host:example.org -lang:de status:2x -linkto:gemini://foo/
+/// ```
+///
+/// Plain words are ranked with BM25; quoted phrases have to appear as-is;
+/// anything prefixed with `-` must not match.
+#[derive(Debug, Default, PartialEq)]
+pub struct Query {
+    terms: Vec<String>,
+    excluded_terms: Vec<String>,
+    phrases: Vec<Vec<String>>,
+    excluded_phrases: Vec<Vec<String>>,
+    /// (filter, negated)
+    filters: Vec<(Filter, bool)>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Filter {
+    Host(String),
+    Lang(String),
+    Mime(String),
+    Status(String),
+    Title(String),
+    LinkTo(String),
+}
+
+pub struct Hit<'a> {
+    pub url: &'a str,
+    pub score: f64,
+}
+
+impl Query {
+    pub fn parse(q: &str) -> Self {
+        let mut query = Query::default();
+        let mut chars = q.chars().peekable();
+
+        loop {
+            while chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+                chars.next();
+            }
+            if chars.peek().is_none() {
+                break;
+            }
+
+            let negated = chars.peek() == Some(&'-');
+            if negated {
+                chars.next();
+            }
+
+            // read a word, where a quoted part may contain whitespace
+            let mut word = String::new();
+            let mut quoted = false;
+            let mut was_quoted = false;
+            while let Some(&c) = chars.peek() {
+                if c == '"' {
+                    quoted = !quoted;
+                    was_quoted = true;
+                } else if c.is_whitespace() && !quoted {
+                    break;
+                } else {
+                    word.push(c);
+                }
+                chars.next();
+            }
+
+            query.add_word(&word, negated, was_quoted);
+        }
+
+        query
+    }
+
+    fn add_word(&mut self, word: &str, negated: bool, quoted: bool) {
+        if let Some(i) = word.find(':') {
+            let value = word[i + 1..].to_string();
+            let filter = match word[..i].to_lowercase().as_str() {
+                "host" => Some(Filter::Host(value.to_lowercase())),
+                "lang" => Some(Filter::Lang(value.to_lowercase())),
+                "mime" => Some(Filter::Mime(value.to_lowercase())),
+                "status" => Some(Filter::Status(value.to_lowercase())),
+                "title" => Some(Filter::Title(value.to_lowercase())),
+                "linkto" => Some(Filter::LinkTo(value)),
+                _ => None,
+            };
+
+            if let Some(f) = filter {
+                if !f.value().is_empty() {
+                    self.filters.push((f, negated));
+                }
+                return;
+            }
+        }
+
+        let terms = tokenize(word);
+        match (quoted && terms.len() > 1, negated) {
+            (true, false) => self.phrases.push(terms),
+            (true, true) => self.excluded_phrases.push(terms),
+            (false, false) => self.terms.extend(terms),
+            (false, true) => self.excluded_terms.extend(terms),
+        }
+    }
+
+    /// Find and rank everything matching the query. If there are no words
+    /// or phrases to look for, every crawled url is a candidate.
+    pub fn run<'a>(&self, index: &'a Index, entries: &'a HashMap<String, UrlInfo>) -> Vec<Hit<'a>> {
+        let mut positive = self.terms.clone();
+        for phrase in &self.phrases {
+            positive.extend(phrase.iter().cloned());
+        }
+
+        let mut hits = if positive.is_empty() {
+            entries.keys().map(|url| Hit { url, score: 0.0 }).collect::<Vec<_>>()
+        } else {
+            index.score(&positive).into_iter()
+                .map(|(doc, score)| Hit { url: index.url(doc), score })
+                .collect::<Vec<_>>()
+        };
+
+        let mut required = Vec::new();
+        for phrase in &self.phrases {
+            required.push(index.containing_phrase(phrase));
+        }
+
+        let mut excluded = HashSet::new();
+        for term in &self.excluded_terms {
+            excluded.extend(index.containing(term));
+        }
+        for phrase in &self.excluded_phrases {
+            excluded.extend(index.containing_phrase(phrase));
+        }
+
+        let linkers = self.filters.iter()
+            .filter_map(|(f, _)| match f {
+                Filter::LinkTo(target) => Some((target.clone(), linking_to(entries, target))),
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+
+        hits.retain(|hit| {
+            let doc = index.doc(hit.url);
+            if let Some(doc) = doc {
+                if excluded.contains(&doc) {
+                    return false;
+                }
+            }
+            if !required.iter().all(|r| doc.map(|d| r.contains(&d)).unwrap_or(false)) {
+                return false;
+            }
+
+            let info = entries.get(hit.url);
+            self.filters.iter()
+                .all(|(f, negated)| f.matches(hit.url, info, &linkers) != *negated)
+        });
+
+        hits.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap()
+            .then_with(|| a.url.cmp(b.url)));
+        hits
+    }
+}
+
+impl Filter {
+    fn value(&self) -> &str {
+        match self {
+            Filter::Host(v) | Filter::Lang(v) | Filter::Mime(v)
+                | Filter::Status(v) | Filter::Title(v) | Filter::LinkTo(v) => v,
+        }
+    }
+
+    fn matches(&self, url: &str, info: Option<&UrlInfo>,
+        linkers: &HashMap<String, HashSet<String>>) -> bool
+    {
+        match self {
+            Filter::Host(host) => host_of(url)
+                .map(|h| host_matches(&h, host))
+                .unwrap_or(false),
+            Filter::LinkTo(target) => linkers.get(target)
+                .map(|l| l.contains(url))
+                .unwrap_or(false),
+            _ => match info {
+                Some(info) => self.matches_info(info),
+                None => false,
+            },
+        }
+    }
+
+    fn matches_info(&self, info: &UrlInfo) -> bool {
+        match self {
+            Filter::Lang(lang) => info.lang.iter()
+                .flat_map(|l| l.split(','))
+                .any(|l| {
+                    let l = l.trim().to_lowercase();
+                    l == *lang || l.starts_with(&format!("{}-", lang))
+                }),
+            Filter::Mime(mime) => {
+                info.response_code / 10 == 2 && Mime::parse(&info.metatext).matches(mime)
+            },
+            Filter::Status(status) => {
+                let code = info.response_code.to_string();
+                let status = status.trim_end_matches('x');
+                !status.is_empty() && code.starts_with(status)
+            },
+            Filter::Title(title) => info.title.as_ref()
+                .map(|t| t.to_lowercase().contains(title.as_str()))
+                .unwrap_or(false),
+            Filter::Host(_) | Filter::LinkTo(_) => unreachable!(),
+        }
+    }
+}
+
+fn host_matches(host: &str, wanted: &str) -> bool {
+    let host = host.to_lowercase();
+    host == wanted || host.ends_with(&format!(".{}", wanted))
+}
+
+/// Pages that link to the target, which is either a full url (gemini or
+/// external) or a capsule hostname.
+fn linking_to(entries: &HashMap<String, UrlInfo>, target: &str) -> HashSet<String> {
+    let mut linkers = HashSet::new();
+
+    if target.contains("://") {
+        let target = crate::parse_url(None, target)
+            .map(|u| u.to_string())
+            .unwrap_or_else(|_| target.to_string());
+
+        if let Some(info) = entries.get(&target) {
+            linkers.extend(info.referred_from.iter().cloned());
+        }
+        for (url, info) in entries {
+            if info.external_links.iter().any(|l| l.target == target) {
+                linkers.insert(url.clone());
+            }
+        }
+    } else {
+        let target = target.to_lowercase();
+        for (url, info) in entries {
+            if host_of(url).map(|h| host_matches(&h, &target)).unwrap_or(false) {
+                linkers.extend(info.referred_from.iter().cloned());
+            }
+            let external = info.external_links.iter()
+                .filter_map(|l| host_of(&l.target))
+                .any(|h| host_matches(&h, &target));
+            if external {
+                linkers.insert(url.clone());
+            }
+        }
+    }
+
+    linkers
+}
+
+/// usage: search <results.json> <index.json> <query...>
+pub fn search(args: &[String]) -> Result<(), Box<dyn Error>> {
+    if args.len() < 3 {
+        Err("usage: search <results.json> <index.json> <query...>")?;
+    }
+
+    let entries = crate::load_data(&args[0])?;
+    let index = Index::load(&args[1])?;
+    let hits = Query::parse(&args[2..].join(" ")).run(&index, &entries);
+
+    for hit in hits.iter().take(20) {
+        let title = entries.get(hit.url).and_then(|i| i.title.as_deref()).unwrap_or("");
+        println!("{:>8.3}  {}  {}", hit.score, hit.url, title);
+    }
+    println!("{} results", hits.len());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let q = Query::parse("rust -go \"tiny web\" -\"big web\" host:Example.org -status:5x title:\"a b\"");
+        assert_eq!(q, Query {
+            terms: vec!["rust".to_string()],
+            excluded_terms: vec!["go".to_string()],
+            phrases: vec![vec!["tiny".to_string(), "web".to_string()]],
+            excluded_phrases: vec![vec!["big".to_string(), "web".to_string()]],
+            filters: vec![
+                (Filter::Host("example.org".to_string()), false),
+                (Filter::Status("5x".to_string()), true),
+                (Filter::Title("a b".to_string()), false),
+            ],
+        });
+    }
+
+    #[test]
+    fn run() {
+        let mut entries = HashMap::new();
+        let mut index = Index::default();
+
+        let mut a = UrlInfo::new("gemini://b.example:1965/".to_string());
+        a.response_code = 20;
+        a.metatext = "text/gemini; lang=en-GB".to_string();
+        a.lang = Some("en-GB".to_string());
+        a.title = Some("About Rust".to_string());
+        entries.insert("gemini://a.example:1965/".to_string(), a);
+        index.add("gemini://a.example:1965/", &gemtext::parse("# About Rust\nthe tiny web"));
+
+        let b = UrlInfo {
+            response_code: 20,
+            metatext: "text/gemini; lang=de".to_string(),
+            lang: Some("de".to_string()),
+            ..Default::default()
+        };
+        entries.insert("gemini://b.example:1965/".to_string(), b);
+        index.add("gemini://b.example:1965/", &gemtext::parse("rust web tiny"));
+
+        let c = UrlInfo { response_code: 51, ..Default::default() };
+        entries.insert("gemini://c.example:1965/gone".to_string(), c);
+
+        let urls = |q: &str| Query::parse(q).run(&index, &entries).iter()
+            .map(|h| h.url.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(urls("rust").len(), 2);
+        assert_eq!(urls("\"tiny web\""), vec!["gemini://a.example:1965/"]);
+        assert_eq!(urls("rust -\"tiny web\""), vec!["gemini://b.example:1965/"]);
+        assert_eq!(urls("rust lang:en"), vec!["gemini://a.example:1965/"]);
+        assert_eq!(urls("title:rust"), vec!["gemini://a.example:1965/"]);
+        assert_eq!(urls("status:5x"), vec!["gemini://c.example:1965/gone"]);
+        assert_eq!(urls("-status:5x mime:text"),
+            vec!["gemini://a.example:1965/", "gemini://b.example:1965/"]);
+        assert_eq!(urls("linkto:a.example"), vec!["gemini://b.example:1965/"]);
+        assert_eq!(urls("host:c.example"), vec!["gemini://c.example:1965/gone"]);
+    }
+}
@@ -0,0 +1,149 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::graph::Graph;
+use crate::UrlInfo;
+
+const DAMPING: f64 = 0.85;
+const MAX_ITERATIONS: usize = 100;
+const EPSILON: f64 = 1.0e-9;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Rank {
+    pub pagerank: f64,
+    pub in_degree: usize,
+    pub out_degree: usize,
+}
+
+/// Compute PageRank and degrees for every vertex of the graph, in the same
+/// order as `graph.vertices`. Links are weighted by how often they occur.
+pub fn rank(graph: &Graph) -> Vec<Rank> {
+    let n = graph.vertices.len();
+    if n == 0 {
+        return Vec::new();
+    }
+
+    let mut ranks = vec![Rank::default(); n];
+    let mut out_weight = vec![0usize; n];
+
+    for (&(from, to), &weight) in &graph.edges {
+        ranks[from].out_degree += 1;
+        ranks[to].in_degree += 1;
+        out_weight[from] += weight;
+    }
+
+    let mut pr = vec![1.0 / n as f64; n];
+
+    for _ in 0..MAX_ITERATIONS {
+        // rank of pages without outgoing links is spread over everyone
+        let dangling = (0..n)
+            .filter(|&i| out_weight[i] == 0)
+            .map(|i| pr[i])
+            .sum::<f64>();
+
+        let base = (1.0 - DAMPING) / n as f64 + DAMPING * dangling / n as f64;
+        let mut next = vec![base; n];
+
+        for (&(from, to), &weight) in &graph.edges {
+            next[to] += DAMPING * pr[from] * weight as f64 / out_weight[from] as f64;
+        }
+
+        let delta = pr.iter().zip(&next)
+            .map(|(a, b)| (a - b).abs())
+            .sum::<f64>();
+        pr = next;
+
+        if delta < EPSILON {
+            break;
+        }
+    }
+
+    for (r, p) in ranks.iter_mut().zip(pr) {
+        r.pagerank = p;
+    }
+
+    ranks
+}
+
+/// Compute page-level ranks and store them with each entry.
+pub fn rank_entries(entries: &mut HashMap<String, UrlInfo>) {
+    let graph = Graph::pages(entries);
+    let ranks = rank(&graph);
+
+    for (vertex, r) in graph.vertices.iter().zip(ranks) {
+        if let Some(info) = entries.get_mut(&vertex.label) {
+            info.rank = Some(r);
+        }
+    }
+}
+
+/// usage: rank [--hosts] [--save] <results.json> [count]
+pub fn list(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let hosts = args.iter().any(|a| a == "--hosts");
+    let save = args.iter().any(|a| a == "--save");
+    let args = args.iter().filter(|a| *a != "--hosts" && *a != "--save").collect::<Vec<_>>();
+
+    if args.is_empty() {
+        Err("usage: rank [--hosts] [--save] <results.json> [count]")?;
+    }
+
+    let count = match args.get(1) {
+        Some(c) => c.parse::<usize>()?,
+        None => 50,
+    };
+
+    let mut entries = crate::load_data(args[0])?;
+
+    // keep the page-level scores with the results, if asked to
+    if save {
+        rank_entries(&mut entries);
+        crate::save_data(args[0], &entries)?;
+    }
+
+    let graph = if hosts { Graph::hosts(&entries) } else { Graph::pages(&entries) };
+    let ranks = rank(&graph);
+
+    let mut order = (0..ranks.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| ranks[b].pagerank.partial_cmp(&ranks[a].pagerank).unwrap());
+
+    println!("{:>4}  {:>10}  {:>6}  {:>6}  {}", "#", "pagerank", "in", "out",
+        if hosts { "capsule" } else { "url" });
+    for (i, &v) in order.iter().take(count).enumerate() {
+        println!("{:>4}  {:>10.6}  {:>6}  {:>6}  {}  {}", i + 1, ranks[v].pagerank,
+            ranks[v].in_degree, ranks[v].out_degree, graph.vertices[v].label,
+            graph.vertices[v].title);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pagerank() {
+        let mut entries = HashMap::new();
+        entries.insert("gemini://hub:1965/".to_string(), UrlInfo::default());
+        for leaf in &["a", "b", "c"] {
+            let mut info = UrlInfo::new("gemini://hub:1965/".to_string());
+            info.referred_from.push(format!("gemini://{}:1965/", "d"));
+            entries.insert(format!("gemini://{}:1965/", leaf), info);
+        }
+        entries.insert("gemini://d:1965/".to_string(),
+            UrlInfo::new("gemini://a:1965/".to_string()));
+
+        rank_entries(&mut entries);
+
+        let sum = entries.values().map(|i| i.rank.as_ref().unwrap().pagerank).sum::<f64>();
+        assert!((sum - 1.0).abs() < 1.0e-6);
+
+        let a = entries["gemini://a:1965/"].rank.clone().unwrap();
+        let hub = entries["gemini://hub:1965/"].rank.clone().unwrap();
+        assert_eq!((a.in_degree, a.out_degree), (2, 1));
+        assert_eq!((hub.in_degree, hub.out_degree), (0, 3));
+        assert!(a.pagerank > hub.pagerank);
+    }
+}
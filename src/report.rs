@@ -0,0 +1,438 @@
+use gemtext::Builder;
+use serde::Serialize;
+use url::Url;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::error::ErrorClass;
+use crate::graph::host_of;
+use crate::identity::ClientCert;
+use crate::input::InputEndpoint;
+use crate::mime::Mime;
+use crate::UrlInfo;
+
+/// Everything we know about a single capsule, summed up over its pages.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct CapsuleReport {
+    /// title of the root page
+    pub title: Option<String>,
+    pub pages: usize,
+    /// response code -> number of pages; 0 means never fetched
+    pub status_codes: BTreeMap<usize, usize>,
+    pub timeouts: usize,
+    pub malformed_responses: usize,
+    /// why pages couldn't be fetched -> number of pages
+    pub errors: BTreeMap<ErrorClass, usize>,
+    /// mime type (without parameters) -> number of pages
+    pub mime_types: BTreeMap<String, usize>,
+    pub bytes: usize,
+    /// links to this capsule from pages on other capsules
+    pub inbound_links: usize,
+    pub referring_hosts: BTreeSet<String>,
+    /// when we first came across one of its pages (unix time)
+    pub first_seen: Option<u64>,
+    /// when we last fetched one of its pages (unix time)
+    pub last_seen: Option<u64>,
+    /// url -> what it asks for
+    pub input_endpoints: BTreeMap<String, InputEndpoint>,
+    /// url -> whether we got in with a client certificate
+    pub cert_areas: BTreeMap<String, ClientCert>,
+    /// what the host resolved to
+    pub addresses: BTreeSet<IpAddr>,
+}
+
+pub fn aggregate(entries: &HashMap<String, UrlInfo>) -> BTreeMap<String, CapsuleReport> {
+    let mut capsules: BTreeMap<String, CapsuleReport> = BTreeMap::new();
+
+    for (url, info) in entries {
+        let host = match host_of(url) {
+            Some(h) => h,
+            None => continue,
+        };
+        let capsule = capsules.entry(host.clone()).or_default();
+
+        if Url::parse(url).map(|u| u.path() == "/").unwrap_or(false) {
+            capsule.title = info.title.clone();
+        }
+
+        capsule.pages += 1;
+        // gopher has no status codes
+        if info.item_type.is_none() {
+            *capsule.status_codes.entry(info.response_code).or_insert(0) += 1;
+        }
+        capsule.bytes += info.bytes;
+
+        if info.timed_out {
+            capsule.timeouts += 1;
+        }
+        if info.malformed_response {
+            capsule.malformed_responses += 1;
+        }
+        if let Some(e) = &info.error {
+            *capsule.errors.entry(e.class).or_insert(0) += 1;
+        }
+
+        if info.response_code == 20 || (info.item_type.is_some() && info.answered()) {
+            let mime = Mime::parse(&info.metatext).essence;
+            *capsule.mime_types.entry(mime).or_insert(0) += 1;
+        }
+
+        for referrer in &info.referred_from {
+            match host_of(referrer) {
+                Some(h) if h != host => {
+                    capsule.inbound_links += 1;
+                    capsule.referring_hosts.insert(h);
+                },
+                _ => (),
+            }
+        }
+
+        if let Some(input) = &info.input {
+            capsule.input_endpoints.insert(url.clone(), input.clone());
+        }
+
+        if let Some(cert) = &info.client_cert {
+            capsule.cert_areas.insert(url.clone(), cert.clone());
+        }
+
+        capsule.addresses.extend(&info.addresses);
+
+        // results from before we kept track of discoveries have the first
+        // fetch at best
+        let discovered = info.discovered_at
+            .or_else(|| info.attempts.first().map(|a| a.at))
+            .or(info.fetched_at);
+        if let Some(t) = discovered {
+            capsule.first_seen = Some(capsule.first_seen.map_or(t, |f| f.min(t)));
+        }
+        if let Some(t) = info.fetched_at {
+            capsule.last_seen = Some(capsule.last_seen.map_or(t, |l| l.max(t)));
+        }
+    }
+
+    capsules
+}
+
+/// Describe a single capsule as a gemtext section.
+pub fn capsule_section(mut doc: Builder, host: &str, capsule: &CapsuleReport) -> Builder {
+    doc = doc.heading(2, host)
+        .link(format!("gemini://{}/", host), capsule.title.clone())
+        .list_item(format!("{} pages, {} bytes", capsule.pages, capsule.bytes))
+        .list_item(format!("{} inbound links from {} other capsules",
+            capsule.inbound_links, capsule.referring_hosts.len()))
+        .list_item(format!("{} timeouts, {} malformed responses",
+            capsule.timeouts, capsule.malformed_responses));
+
+    let codes = capsule.status_codes.iter()
+        .map(|(code, n)| format!("{}: {}", code, n))
+        .collect::<Vec<_>>();
+    doc = doc.list_item(format!("status codes: {}", codes.join(", ")));
+
+    if !capsule.errors.is_empty() {
+        let errors = capsule.errors.iter()
+            .map(|(class, n)| format!("{}: {}", class, n))
+            .collect::<Vec<_>>();
+        doc = doc.list_item(format!("errors: {}", errors.join(", ")));
+    }
+
+    if !capsule.mime_types.is_empty() {
+        let mimes = capsule.mime_types.iter()
+            .map(|(mime, n)| format!("{}: {}", mime, n))
+            .collect::<Vec<_>>();
+        doc = doc.list_item(format!("mime types: {}", mimes.join(", ")));
+    }
+
+    if !capsule.addresses.is_empty() {
+        let addrs = capsule.addresses.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        doc = doc.list_item(format!("addresses: {}", addrs.join(", ")));
+    }
+
+    if let (Some(first), Some(last)) = (capsule.first_seen, capsule.last_seen) {
+        doc = doc.list_item(format!("first seen at {}, last fetched at {} (unix time)", first, last));
+    }
+
+    if !capsule.input_endpoints.is_empty() {
+        doc = doc.heading(3, "Input endpoints");
+        for (url, input) in &capsule.input_endpoints {
+            let sensitive = if input.sensitive { ", sensitive" } else { "" };
+            doc = doc.link(url.as_str(), Some(format!("{:?}{}: {}",
+                input.kind, sensitive, input.prompt)));
+        }
+    }
+
+    if !capsule.cert_areas.is_empty() {
+        doc = doc.heading(3, "Pages requiring a client certificate");
+        for (url, cert) in &capsule.cert_areas {
+            let outcome = match (cert.presented, cert.failed, cert.accepted) {
+                (false, _, _) => "none presented",
+                (true, true, _) => "no answer with one",
+                (true, false, true) => "accepted",
+                (true, false, false) => "rejected",
+            };
+            doc = doc.link(url.as_str(), Some(format!("{}: {}", cert.required, outcome)));
+        }
+    }
+
+    doc
+}
+
+/// address -> hosts on it, for addresses shared by more than one capsule
+pub fn clusters(capsules: &BTreeMap<String, CapsuleReport>) -> BTreeMap<IpAddr, BTreeSet<String>> {
+    let mut clusters: BTreeMap<IpAddr, BTreeSet<String>> = BTreeMap::new();
+
+    for (host, capsule) in capsules {
+        for addr in &capsule.addresses {
+            clusters.entry(*addr).or_default().insert(host.clone());
+        }
+    }
+
+    clusters.retain(|_, hosts| hosts.len() > 1);
+    clusters
+}
+
+/// usage: clusters <results.json>
+pub fn list_clusters(args: &[String]) -> Result<(), Box<dyn Error>> {
+    if args.is_empty() {
+        Err("usage: clusters <results.json>")?;
+    }
+
+    let entries = crate::load_data(&args[0])?;
+    let mut clusters = clusters(&aggregate(&entries)).into_iter().collect::<Vec<_>>();
+    clusters.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(&b.0)));
+
+    let mut doc = Builder::new().heading(1, "Shared addresses");
+    if clusters.is_empty() {
+        doc = doc.text("No address is shared by more than one capsule.");
+    }
+    for (addr, hosts) in clusters {
+        doc = doc.heading(2, format!("{} ({} capsules)", addr, hosts.len()));
+        for host in hosts {
+            doc = doc.link(format!("gemini://{}/", host), Some(host));
+        }
+    }
+
+    gemtext::render(doc.build(), &mut std::io::stdout())?;
+    Ok(())
+}
+
+/// usage: report <results.json> [outdir]
+pub fn capsules(args: &[String]) -> Result<(), Box<dyn Error>> {
+    if args.is_empty() {
+        Err("usage: report <results.json> [outdir]")?;
+    }
+
+    let outdir = Path::new(args.get(1).map(|d| d.as_str()).unwrap_or("."));
+    let entries = crate::load_data(&args[0])?;
+    let capsules = aggregate(&entries);
+
+    let json_path = outdir.join("capsules.json");
+    fs::write(&json_path, serde_json::to_string_pretty(&capsules)?)?;
+
+    let mut doc = Builder::new()
+        .heading(1, "Capsules")
+        .text(format!("{} capsules, {} pages.", capsules.len(), entries.len()));
+    for (host, capsule) in &capsules {
+        doc = doc.text("");
+        doc = capsule_section(doc, host, capsule);
+    }
+
+    let gmi_path = outdir.join("capsules.gmi");
+    let mut out = fs::File::create(&gmi_path)?;
+    gemtext::render(doc.build(), &mut out)?;
+
+    println!("wrote {} and {}", json_path.display(), gmi_path.display());
+    Ok(())
+}
+
+/// Why a link is considered broken, if it is.
+pub fn failure_reason(info: &UrlInfo) -> Option<String> {
+    if info.timed_out {
+        return Some("timed out".to_string());
+    }
+    if info.malformed_response {
+        return Some("malformed response".to_string());
+    }
+    if let Some(e) = &info.error {
+        return Some(e.to_string());
+    }
+
+    let reason = match info.response_code {
+        50 => "permanent failure",
+        51 => "not found",
+        52 => "gone",
+        53 => "proxy request refused",
+        59 => "bad request",
+        _ => return None,
+    };
+
+    Some(format!("{} {}", info.response_code, reason))
+}
+
+/// host -> referring page -> [(broken link, reason)]
+pub type BrokenLinks = BTreeMap<String, BTreeMap<String, Vec<(String, String)>>>;
+
+/// Collect broken links by the capsule and page they are found on,
+/// optionally only for a single capsule.
+pub fn broken_links(entries: &HashMap<String, UrlInfo>, only: Option<&str>) -> BrokenLinks {
+    let mut broken = BrokenLinks::new();
+
+    for (url, info) in entries {
+        let reason = match failure_reason(info) {
+            Some(r) => r,
+            None => continue,
+        };
+
+        for referrer in &info.referred_from {
+            let host = match host_of(referrer) {
+                Some(h) => h,
+                None => continue,
+            };
+            if only.map(|o| o != host).unwrap_or(false) {
+                continue;
+            }
+
+            broken.entry(host).or_default()
+                .entry(referrer.clone()).or_default()
+                .push((url.clone(), reason.clone()));
+        }
+    }
+
+    for pages in broken.values_mut() {
+        for links in pages.values_mut() {
+            links.sort();
+            links.dedup();
+        }
+    }
+
+    broken
+}
+
+/// Describe the broken links on a single capsule as a gemtext section.
+pub fn broken_section(mut doc: Builder, entries: &HashMap<String, UrlInfo>, host: &str,
+    pages: &BTreeMap<String, Vec<(String, String)>>) -> Builder
+{
+    let count = pages.values().map(|l| l.len()).sum::<usize>();
+    doc = doc.heading(2, host)
+        .text(format!("{} broken links on {} pages.", count, pages.len()));
+
+    for (page, links) in pages {
+        match entries.get(page).and_then(|i| i.title.as_ref()) {
+            Some(title) => doc = doc.heading(3, title.as_str()).link(page.as_str(), None),
+            None => doc = doc.heading(3, page.as_str()),
+        }
+        for (link, reason) in links {
+            doc = doc.link(link.as_str(), Some(reason.clone()));
+        }
+    }
+
+    doc
+}
+
+/// usage: broken <results.json> [host]
+pub fn broken(args: &[String]) -> Result<(), Box<dyn Error>> {
+    if args.is_empty() {
+        Err("usage: broken <results.json> [host]")?;
+    }
+
+    let entries = crate::load_data(&args[0])?;
+    let broken = broken_links(&entries, args.get(1).map(|h| h.as_str()));
+
+    let mut doc = Builder::new().heading(1, "Broken links");
+    if broken.is_empty() {
+        doc = doc.text("No broken links found.");
+    }
+    for (host, pages) in &broken {
+        doc = doc.text("");
+        doc = broken_section(doc, &entries, host, pages);
+    }
+
+    gemtext::render(doc.build(), &mut std::io::stdout())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregate_capsule() {
+        let mut entries = HashMap::new();
+
+        let mut root = UrlInfo::new("gemini://other.example:1965/".to_string());
+        root.response_code = 20;
+        root.metatext = "text/gemini; lang=en".to_string();
+        root.bytes = 100;
+        root.discovered_at = Some(5);
+        root.fetched_at = Some(10);
+        entries.insert("gemini://a.example:1965/".to_string(), root);
+
+        let mut gone = UrlInfo::new("gemini://a.example:1965/".to_string());
+        gone.response_code = 51;
+        gone.discovered_at = Some(6);
+        gone.fetched_at = Some(20);
+        entries.insert("gemini://a.example:1965/gone".to_string(), gone);
+
+        let capsules = aggregate(&entries);
+        let a = &capsules["a.example"];
+        assert_eq!(a.pages, 2);
+        assert_eq!(a.status_codes[&20], 1);
+        assert_eq!(a.status_codes[&51], 1);
+        assert_eq!(a.mime_types["text/gemini"], 1);
+        assert_eq!(a.inbound_links, 1);
+        assert_eq!((a.first_seen, a.last_seen), (Some(5), Some(20)));
+
+        // fetched again later, and from before we kept discovery times
+        let mut again = UrlInfo { discovered_at: None, fetched_at: Some(30), ..Default::default() };
+        again.attempts.push(crate::retry::Attempt::of(&UrlInfo { fetched_at: Some(2), ..Default::default() }));
+        entries.insert("gemini://a.example:1965/again".to_string(), again);
+        let a = &aggregate(&entries)["a.example"];
+        assert_eq!((a.first_seen, a.last_seen), (Some(2), Some(30)));
+    }
+
+    #[test]
+    fn shared_addresses() {
+        let mut entries = HashMap::new();
+        let shared: IpAddr = "192.0.2.1".parse().unwrap();
+        for (url, addr) in &[
+            ("gemini://a.example:1965/", shared),
+            ("gemini://b.example:1965/", shared),
+            ("gemini://c.example:1965/", "192.0.2.2".parse().unwrap()),
+        ] {
+            let info = UrlInfo { addresses: vec![*addr], ..Default::default() };
+            entries.insert(url.to_string(), info);
+        }
+
+        let clusters = clusters(&aggregate(&entries));
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[&shared].iter().collect::<Vec<_>>(), vec!["a.example", "b.example"]);
+    }
+
+    #[test]
+    fn broken() {
+        let mut entries = HashMap::new();
+        entries.insert("gemini://a.example:1965/".to_string(), UrlInfo::default());
+
+        let mut gone = UrlInfo::new("gemini://a.example:1965/".to_string());
+        gone.referred_from.push("gemini://b.example:1965/".to_string());
+        gone.response_code = 52;
+        entries.insert("gemini://c.example:1965/gone".to_string(), gone);
+
+        let mut slow = UrlInfo::new("gemini://a.example:1965/".to_string());
+        slow.timed_out = true;
+        entries.insert("gemini://c.example:1965/slow".to_string(), slow);
+
+        let all = broken_links(&entries, None);
+        assert_eq!(all.len(), 2);
+
+        let a = broken_links(&entries, Some("a.example"));
+        let links = &a["a.example"]["gemini://a.example:1965/"];
+        assert_eq!(links, &vec![
+            ("gemini://c.example:1965/gone".to_string(), "52 gone".to_string()),
+            ("gemini://c.example:1965/slow".to_string(), "timed out".to_string()),
+        ]);
+    }
+}
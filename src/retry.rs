@@ -0,0 +1,138 @@
+use serde::{Deserialize, Serialize};
+
+use std::cell::Cell;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::RetryConfig;
+use crate::error::ErrorClass;
+use crate::UrlInfo;
+
+/// One try at fetching a page, and how it went.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Attempt {
+    pub at: u64,
+    pub timed_out: bool,
+    pub error: Option<ErrorClass>,
+    /// 0 if there was no response
+    pub response_code: usize,
+}
+
+impl Attempt {
+    pub fn of(info: &UrlInfo) -> Self {
+        Self {
+            at: info.fetched_at.unwrap_or(0),
+            timed_out: info.timed_out,
+            error: info.error.as_ref().map(|e| e.class),
+            response_code: info.response_code,
+        }
+    }
+
+    fn failed(&self) -> bool {
+        self.timed_out || self.error.is_some() || self.response_code / 10 == 4
+    }
+}
+
+/// Decides whether and when to try a page again.
+pub struct Policy {
+    cfg: RetryConfig,
+    rng: Cell<u64>,
+}
+
+impl Policy {
+    pub fn new(cfg: RetryConfig) -> Self {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Self { cfg, rng: Cell::new(seed | 1) }
+    }
+
+    /// How many times we may try a page that failed like this one did, or
+    /// `None` if that kind of failure isn't worth retrying.
+    fn max_attempts(&self, info: &UrlInfo) -> Option<usize> {
+        if info.timed_out {
+            return Some(self.cfg.timeout);
+        }
+        if let Some(e) = &info.error {
+            return self.cfg.errors.get(&e.class).copied();
+        }
+        self.cfg.statuses.get(&info.response_code).copied()
+    }
+
+    /// How long to wait before trying the page again, if we should.
+    pub fn retry_after(&self, info: &UrlInfo) -> Option<Duration> {
+        // only count the failures since the page last answered properly,
+        // e.g. in an earlier crawl
+        let attempts = info.attempts.iter().rev().take_while(|a| a.failed()).count();
+        if attempts == 0 || attempts >= self.max_attempts(info)? {
+            return None;
+        }
+
+        // base * 2^(attempts - 1), with up to half of it added at random
+        let base = self.cfg.base_delay_ms.saturating_mul(1 << (attempts - 1).min(16));
+        let mut delay = base.min(self.cfg.max_delay_ms);
+        delay += self.random() % (delay / 2 + 1);
+
+        // 44 (slow down) says how many seconds to wait
+        if info.response_code == 44 {
+            if let Ok(secs) = info.metatext.trim().parse::<u64>() {
+                delay = delay.max(secs * 1000);
+            }
+        }
+
+        Some(Duration::from_millis(delay))
+    }
+
+    // xorshift; good enough for jitter
+    fn random(&self) -> u64 {
+        let mut x = self.rng.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng.set(x);
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::FetchError;
+
+    #[test]
+    fn backoff() {
+        let policy = Policy::new(RetryConfig {
+            base_delay_ms: 1000,
+            max_delay_ms: 3000,
+            ..Default::default()
+        });
+
+        let mut info = UrlInfo { timed_out: true, ..Default::default() };
+        let mut delays = Vec::new();
+        loop {
+            info.attempts.push(Attempt::of(&info));
+            match policy.retry_after(&info) {
+                Some(d) => delays.push(d.as_millis() as u64),
+                None => break,
+            }
+        }
+        assert_eq!(delays.len(), RetryConfig::default().timeout - 1);
+        assert!((1000..=1500).contains(&delays[0]));
+        assert!((2000..=3000).contains(&delays[1]));
+
+        // a success in between starts the count over
+        info.attempts.push(Attempt::of(&UrlInfo { response_code: 20, ..Default::default() }));
+        info.attempts.push(Attempt::of(&info));
+        assert!(policy.retry_after(&info).is_some());
+
+        let mut refused = UrlInfo {
+            error: Some(FetchError::new(ErrorClass::ConnectionRefused, "x")),
+            ..Default::default()
+        };
+        refused.attempts.push(Attempt::of(&refused));
+        assert!(policy.retry_after(&refused).is_none());
+
+        let mut slow = UrlInfo { response_code: 44, metatext: "30".to_string(), ..Default::default() };
+        slow.attempts.push(Attempt::of(&slow));
+        assert!(policy.retry_after(&slow).unwrap() >= Duration::from_secs(30));
+    }
+}
@@ -0,0 +1,330 @@
+use gemtext::{Builder, Node};
+use log::{info, warn};
+use percent_encoding::percent_decode_str;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
+use url::Url;
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::index::Index;
+use crate::query::Query;
+use crate::report::{self, CapsuleReport};
+use crate::UrlInfo;
+
+const DEFAULT_LISTEN: &str = "0.0.0.0:1965";
+const REQUEST_TIMEOUT_MS: u64 = 5000;
+const MAX_REQUEST_LEN: usize = 1024;
+const MAX_RESULTS: usize = 50;
+
+struct State {
+    entries: HashMap<String, UrlInfo>,
+    index: Index,
+    capsules: BTreeMap<String, CapsuleReport>,
+}
+
+enum Response {
+    Input(&'static str),
+    Success(Vec<Node>),
+    NotFound,
+    BadRequest(&'static str),
+}
+
+/// usage: serve <results.json> <index.json> [--listen addr] [--cert cert.pem --key key.pem]
+pub fn serve(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut listen = DEFAULT_LISTEN.to_string();
+    let mut cert = None;
+    let mut key = None;
+    let mut files = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().ok_or("--listen needs an address")?.clone(),
+            "--cert" => cert = Some(args.next().ok_or("--cert needs a path")?.clone()),
+            "--key" => key = Some(args.next().ok_or("--key needs a path")?.clone()),
+            _ => files.push(arg.clone()),
+        }
+    }
+
+    if files.len() != 2 {
+        Err("usage: serve <results.json> <index.json> [--listen addr] [--cert cert.pem --key key.pem]")?;
+    }
+
+    let (chain, key) = match (cert, key) {
+        (Some(c), Some(k)) => crate::cert::load_pem(&c, &k)?,
+        (None, None) => {
+            info!("no certificate given, generating a self-signed one for localhost");
+            crate::cert::generate(vec!["localhost".to_string()])?
+        },
+        _ => Err("--cert and --key have to be given together")?,
+    };
+
+    let mut cfg = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+    cfg.set_single_cert(chain, key)?;
+    let acceptor = TlsAcceptor::from(Arc::new(cfg));
+
+    let entries = crate::load_data(&files[0])?;
+    let index = Index::load(&files[1])?;
+    let capsules = report::aggregate(&entries);
+    let state = Arc::new(State { entries, index, capsules });
+
+    smol::run(async move {
+        let mut listener = TcpListener::bind(&listen).await?;
+        info!("listening on {}", listen);
+
+        loop {
+            let (sock, peer) = match listener.accept().await {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("{}", e);
+                    continue;
+                },
+            };
+            let acceptor = acceptor.clone();
+            let state = state.clone();
+
+            smol::Task::spawn(async move {
+                if let Err(e) = handle(acceptor, sock, &state).await {
+                    warn!("{}: {}", peer, e);
+                }
+            }).detach();
+        }
+    })
+}
+
+async fn handle(acceptor: TlsAcceptor, sock: TcpStream, state: &State)
+    -> Result<(), Box<dyn Error>>
+{
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::time::timeout;
+
+    let wait = Duration::from_millis(REQUEST_TIMEOUT_MS);
+    let mut tls = timeout(wait, acceptor.accept(sock)).await??;
+
+    // read up to the end of the request line
+    let mut request = Vec::new();
+    let mut buf = [0u8; 512];
+    let read = async {
+        while !request.ends_with(b"\r\n") && request.len() <= MAX_REQUEST_LEN + 2 {
+            let n = tls.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+        Ok::<_, std::io::Error>(())
+    };
+    timeout(wait, read).await??;
+
+    let response = match request_url(&request) {
+        Some(url) => respond(state, &url),
+        None => Response::BadRequest("Bad request"),
+    };
+
+    let mut out = Vec::new();
+    match response {
+        Response::Input(prompt) => out.extend(format!("10 {}\r\n", prompt).bytes()),
+        Response::Success(nodes) => {
+            out.extend(b"20 text/gemini\r\n");
+            gemtext::render(nodes, &mut out)?;
+        },
+        Response::NotFound => out.extend(b"51 Not found\r\n"),
+        Response::BadRequest(why) => out.extend(format!("59 {}\r\n", why).bytes()),
+    }
+
+    tls.write_all(&out).await?;
+    tls.shutdown().await?;
+    Ok(())
+}
+
+fn request_url(request: &[u8]) -> Option<Url> {
+    if !request.ends_with(b"\r\n") || request.len() > MAX_REQUEST_LEN + 2 {
+        return None;
+    }
+
+    let line = std::str::from_utf8(&request[..request.len() - 2]).ok()?;
+    Url::parse(line).ok()
+}
+
+/// The decoded query, without control characters: it ends up in headings,
+/// where an encoded line break would start lines of the client's choosing.
+fn query(url: &Url) -> Option<String> {
+    match url.query() {
+        Some(q) if !q.is_empty() => Some(percent_decode_str(q).decode_utf8_lossy()
+            .chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect()),
+        _ => None,
+    }
+}
+
+fn respond(state: &State, url: &Url) -> Response {
+    match (url.path(), query(url)) {
+        ("/", _) | ("", _) => Response::Success(home(state)),
+        ("/search", None) => Response::Input("Search query"),
+        ("/search", Some(q)) => Response::Success(search(state, &q)),
+        ("/backlinks", None) => Response::Input("URL to list backlinks for"),
+        ("/backlinks", Some(q)) => Response::Success(backlinks(state, &q)),
+        ("/capsules", _) => Response::Success(capsules(state)),
+        ("/capsule", Some(host)) => match capsule(state, &host) {
+            Some(nodes) => Response::Success(nodes),
+            None => Response::NotFound,
+        },
+        ("/capsule", None) => Response::Input("Capsule hostname"),
+        _ => Response::NotFound,
+    }
+}
+
+fn home(state: &State) -> Vec<Node> {
+    Builder::new()
+        .heading(1, "curiosity")
+        .text(format!("{} pages crawled on {} capsules, {} of them indexed.",
+            state.entries.len(), state.capsules.len(), state.index.len()))
+        .text("")
+        .link("/search", Some("Search geminispace".to_string()))
+        .link("/backlinks", Some("Backlinks for a URL".to_string()))
+        .link("/capsules", Some("All capsules".to_string()))
+        .build()
+}
+
+fn search(state: &State, q: &str) -> Vec<Node> {
+    let hits = Query::parse(q).run(&state.index, &state.entries);
+
+    let mut doc = Builder::new()
+        .heading(1, format!("Results for '{}'", q))
+        .text(format!("{} results.", hits.len()))
+        .text("");
+    for hit in hits.iter().take(MAX_RESULTS) {
+        let title = state.entries.get(hit.url).and_then(|i| i.title.clone());
+        doc = doc.link(hit.url, title);
+    }
+
+    doc.text("")
+        .text("Search operators: host: lang: mime: status: title: linkto: \"a phrase\" -excluded")
+        .link("/search", Some("Search again".to_string()))
+        .link("/", Some("Home".to_string()))
+        .build()
+}
+
+fn backlinks(state: &State, q: &str) -> Vec<Node> {
+    let url = crate::parse_url(None, q.trim())
+        .map(|u| u.to_string())
+        .unwrap_or_else(|_| q.to_string());
+
+    let mut doc = Builder::new().heading(1, format!("Backlinks for {}", url));
+
+    match state.entries.get(&url) {
+        Some(info) if !info.referred_from.is_empty() => {
+            let mut referrers = info.referred_from.clone();
+            referrers.sort();
+            referrers.dedup();
+
+            doc = doc.text(format!("{} pages link here.", referrers.len())).text("");
+            for referrer in referrers {
+                doc = doc.link(referrer, None);
+            }
+        },
+        _ => doc = doc.text("No backlinks known."),
+    }
+
+    doc.text("")
+        .link("/backlinks", Some("Look up another URL".to_string()))
+        .link("/", Some("Home".to_string()))
+        .build()
+}
+
+fn capsules(state: &State) -> Vec<Node> {
+    let mut doc = Builder::new()
+        .heading(1, "Capsules")
+        .text(format!("{} capsules.", state.capsules.len()))
+        .text("");
+
+    for (host, capsule) in &state.capsules {
+        doc = doc.link(format!("/capsule?{}", host),
+            Some(format!("{} ({} pages)", host, capsule.pages)));
+    }
+
+    doc.link("/", Some("Home".to_string())).build()
+}
+
+fn capsule(state: &State, host: &str) -> Option<Vec<Node>> {
+    let capsule = state.capsules.get(host)?;
+
+    let mut doc = Builder::new().heading(1, "Capsule report");
+    doc = report::capsule_section(doc, host, capsule);
+
+    let broken = report::broken_links(&state.entries, Some(host));
+    if let Some(pages) = broken.get(host) {
+        doc = doc.text("");
+        doc = report::broken_section(doc, &state.entries, "Broken links", pages);
+    }
+
+    Some(doc.text("")
+        .link("/capsules", Some("All capsules".to_string()))
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> State {
+        let mut entries = HashMap::new();
+        let page = UrlInfo { response_code: 20, ..Default::default() };
+        entries.insert("gemini://a.example/".to_string(), page);
+        let capsules = report::aggregate(&entries);
+        State { entries, index: Index::default(), capsules }
+    }
+
+    fn get(state: &State, url: &str) -> Response {
+        respond(state, &Url::parse(url).unwrap())
+    }
+
+    fn text(response: Response) -> String {
+        let mut out = Vec::new();
+        match response {
+            Response::Success(nodes) => gemtext::render(nodes, &mut out).unwrap(),
+            _ => panic!("not a success"),
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn requests() {
+        assert!(request_url(b"gemini://a.example/search?x\r\n").is_some());
+        assert!(request_url(b"gemini://a.example/").is_none());
+        assert!(request_url(b"not a url\r\n").is_none());
+        assert!(request_url(b"gemini://a.example/\xff\r\n").is_none());
+
+        let long = format!("gemini://a.example/{}\r\n", "x".repeat(MAX_REQUEST_LEN));
+        assert!(request_url(long.as_bytes()).is_none());
+    }
+
+    #[test]
+    fn routes() {
+        let state = state();
+        assert!(matches!(get(&state, "gemini://a.example/search"), Response::Input(_)));
+        assert!(matches!(get(&state, "gemini://a.example/backlinks"), Response::Input(_)));
+        assert!(matches!(get(&state, "gemini://a.example/capsule?b.example"), Response::NotFound));
+        assert!(matches!(get(&state, "gemini://a.example/elsewhere"), Response::NotFound));
+
+        assert!(text(get(&state, "gemini://a.example/capsules")).contains("a.example (1 pages)"));
+        assert!(text(get(&state, "gemini://a.example/capsule?a.example")).starts_with("# Capsule report"));
+    }
+
+    #[test]
+    fn no_injected_lines() {
+        let state = state();
+        for url in &[
+            "gemini://a.example/search?x%0D%0A=%3E%20gemini://evil.example/",
+            "gemini://a.example/backlinks?x%0A=%3E%20gemini://evil.example/",
+        ] {
+            let text = text(get(&state, url));
+            assert!(!text.lines().any(|l| l.starts_with("=> gemini://evil")), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use log::warn;
+use tokio::sync::watch;
+
+use std::error::Error;
+use std::time::Duration;
+
+/// Whether we've been asked to stop (with SIGINT or SIGTERM). The first
+/// signal lets the crawl wind down; a second one quits right away.
+#[derive(Clone)]
+pub struct Shutdown {
+    rx: watch::Receiver<bool>,
+}
+
+/// Start listening for signals. Has to be called from within the runtime.
+pub fn listen() -> Result<Shutdown, Box<dyn Error>> {
+    let (tx, rx) = watch::channel(false);
+    let mut signals = Signals::new()?;
+
+    smol::Task::spawn(async move {
+        signals.recv().await;
+        warn!("stopping: finishing what's in flight and saving; signal again to quit now");
+        let _ = tx.broadcast(true);
+
+        signals.recv().await;
+        warn!("quitting without saving");
+        std::process::exit(130);
+    }).detach();
+
+    Ok(Shutdown { rx })
+}
+
+impl Shutdown {
+    pub fn requested(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Wait until we're asked to stop.
+    pub async fn wait(&self) {
+        let mut rx = self.rx.clone();
+        while !*rx.borrow() {
+            if rx.recv().await.is_none() {
+                // nobody can ask anymore
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    /// Wait until `grace` after we're asked to stop.
+    pub async fn deadline(&self, grace: Duration) {
+        self.wait().await;
+        tokio::time::delay_for(grace).await;
+    }
+}
+
+#[cfg(unix)]
+struct Signals {
+    int: tokio::signal::unix::Signal,
+    term: tokio::signal::unix::Signal,
+}
+
+#[cfg(unix)]
+impl Signals {
+    fn new() -> Result<Self, Box<dyn Error>> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(Self {
+            int: signal(SignalKind::interrupt())?,
+            term: signal(SignalKind::terminate())?,
+        })
+    }
+
+    async fn recv(&mut self) {
+        tokio::select! {
+            _ = self.int.recv() => (),
+            _ = self.term.recv() => (),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct Signals;
+
+#[cfg(not(unix))]
+impl Signals {
+    fn new() -> Result<Self, Box<dyn Error>> {
+        Ok(Self)
+    }
+
+    async fn recv(&mut self) {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn stop() {
+        let (tx, rx) = watch::channel(false);
+        let shutdown = Shutdown { rx };
+        assert!(!shutdown.requested());
+
+        tx.broadcast(true).unwrap();
+        assert!(shutdown.requested());
+
+        smol::run(async {
+            shutdown.wait().await;
+            let start = Instant::now();
+            shutdown.deadline(Duration::from_millis(50)).await;
+            assert!(start.elapsed() >= Duration::from_millis(50));
+        });
+    }
+}
@@ -0,0 +1,117 @@
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use std::net::IpAddr;
+
+use crate::error::{ErrorClass, FetchError};
+use crate::net::{connect_string, Host};
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const CONNECT: u8 = 1;
+
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// Open a connection to host:port through the SOCKS5 proxy at `proxy`.
+/// Names are sent to the proxy as they are, so it resolves them.
+pub async fn connect(proxy: &str, host: &Host, port: u16) -> Result<TcpStream, FetchError> {
+    let context = format!("socks proxy {}", proxy);
+    let failed = |e: &str| FetchError::new(ErrorClass::Proxy, format!("{}: {}", context, e));
+    let io = |e| FetchError::new(ErrorClass::Proxy, format!("{}: {}", context, e));
+
+    let mut sock = TcpStream::connect(proxy).await.map_err(io)?;
+
+    sock.write_all(&[VERSION, 1, NO_AUTH]).await.map_err(io)?;
+    let mut choice = [0u8; 2];
+    sock.read_exact(&mut choice).await.map_err(io)?;
+    if choice != [VERSION, NO_AUTH] {
+        return Err(failed("no acceptable authentication method"));
+    }
+
+    sock.write_all(&request(host, port)?).await.map_err(io)?;
+    let mut reply = [0u8; 4];
+    sock.read_exact(&mut reply).await.map_err(io)?;
+    if reply[0] != VERSION {
+        return Err(failed("bad reply"));
+    }
+    match reply[1] {
+        0 => (),
+        // the proxy tells us what happened to the connection it made
+        3 | 4 => return Err(FetchError::new(ErrorClass::Unreachable,
+            format!("{}: {}", connect_string(host, port), reply_error(reply[1])))),
+        5 => return Err(FetchError::new(ErrorClass::ConnectionRefused,
+            format!("{}: {}", connect_string(host, port), reply_error(5)))),
+        code => return Err(failed(reply_error(code))),
+    }
+
+    // skip the address the proxy bound, and its port
+    let len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => sock.read_u8().await.map_err(io)? as usize,
+        _ => return Err(failed("bad reply")),
+    };
+    let mut bound = vec![0u8; len + 2];
+    sock.read_exact(&mut bound).await.map_err(io)?;
+
+    Ok(sock)
+}
+
+/// The CONNECT request for host:port.
+fn request(host: &Host, port: u16) -> Result<Vec<u8>, FetchError> {
+    let mut req = vec![VERSION, CONNECT, 0];
+
+    match host {
+        Host::Ip(IpAddr::V4(ip)) => {
+            req.push(ATYP_IPV4);
+            req.extend(&ip.octets());
+        },
+        Host::Ip(IpAddr::V6(ip)) => {
+            req.push(ATYP_IPV6);
+            req.extend(&ip.octets());
+        },
+        Host::Name(name) => {
+            if name.len() > 255 {
+                return Err(FetchError::new(ErrorClass::InvalidHost,
+                    format!("host name too long for socks: {}", name)));
+            }
+            req.push(ATYP_DOMAIN);
+            req.push(name.len() as u8);
+            req.extend(name.as_bytes());
+        },
+    }
+
+    req.extend(&port.to_be_bytes());
+    Ok(req)
+}
+
+fn reply_error(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests() {
+        let name = Host::Name("abc.onion".to_string());
+        assert_eq!(request(&name, 1965).unwrap(),
+            [&[5, 1, 0, 3, 9][..], b"abc.onion", &[0x07, 0xad]].concat());
+
+        let ip = Host::Ip("127.0.0.1".parse().unwrap());
+        assert_eq!(request(&ip, 70).unwrap(), vec![5, 1, 0, 1, 127, 0, 0, 1, 0, 70]);
+    }
+}